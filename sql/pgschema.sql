@@ -0,0 +1,69 @@
+-- pgschema.sql: PostgreSQL migration 1, the tables of polschema.sql. Raw amounts stay decimal
+-- TEXT (they exceed BIGINT) and are summed exactly by the indexer.
+CREATE TABLE IF NOT EXISTS transfers (
+  tx_hash TEXT NOT NULL,
+  log_index BIGINT NOT NULL,
+  call_path TEXT,
+  block_number BIGINT NOT NULL,
+  timestamp TEXT NOT NULL,
+  timestamp_unix BIGINT,
+  from_addr TEXT NOT NULL,
+  to_addr TEXT NOT NULL,
+  token_address TEXT NOT NULL,
+  amount_raw TEXT NOT NULL,
+  amount DOUBLE PRECISION NOT NULL,
+  tx_fee_raw TEXT,
+  receipt_status INTEGER,
+  PRIMARY KEY (tx_hash, log_index)
+);
+
+CREATE INDEX IF NOT EXISTS idx_transfers_to ON transfers(to_addr);
+CREATE INDEX IF NOT EXISTS idx_transfers_from ON transfers(from_addr);
+CREATE INDEX IF NOT EXISTS idx_transfers_block ON transfers(block_number);
+CREATE INDEX IF NOT EXISTS idx_transfers_time ON transfers(timestamp_unix);
+
+CREATE TABLE IF NOT EXISTS net_flow (
+  exchange TEXT NOT NULL,
+  token_address TEXT NOT NULL,
+  cumulative_amount_raw TEXT NOT NULL,
+  cumulative_amount DOUBLE PRECISION NOT NULL,
+  last_updated TEXT NOT NULL,
+  finalized_amount_raw TEXT,
+  finalized_amount DOUBLE PRECISION,
+  PRIMARY KEY (exchange, token_address)
+);
+
+CREATE TABLE IF NOT EXISTS metadata (
+  key TEXT PRIMARY KEY,
+  value TEXT
+);
+
+CREATE TABLE IF NOT EXISTS blocks (
+  block_number BIGINT PRIMARY KEY,
+  block_hash TEXT NOT NULL,
+  parent_hash TEXT NOT NULL,
+  status TEXT NOT NULL DEFAULT 'pending'
+);
+
+CREATE TABLE IF NOT EXISTS quarantined_blocks (
+  block_number BIGINT NOT NULL,
+  block_hash TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  answers TEXT NOT NULL,
+  detected_at TEXT NOT NULL,
+  PRIMARY KEY (block_number, block_hash, kind)
+);
+
+CREATE TABLE IF NOT EXISTS net_flow_history (
+  exchange TEXT NOT NULL,
+  token_address TEXT NOT NULL,
+  granularity TEXT NOT NULL,
+  bucket_start BIGINT NOT NULL,
+  inflow_raw TEXT NOT NULL,
+  outflow_raw TEXT NOT NULL,
+  net_raw TEXT NOT NULL,
+  inflow DOUBLE PRECISION NOT NULL,
+  outflow DOUBLE PRECISION NOT NULL,
+  net DOUBLE PRECISION NOT NULL,
+  PRIMARY KEY (exchange, token_address, granularity, bucket_start)
+);
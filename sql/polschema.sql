@@ -1,9 +1,12 @@
--- polschema.sql
+-- polschema.sql: SQLite migration 1. Released migrations are never edited; later schema
+-- changes are new entries in src/migrations.rs.
 CREATE TABLE IF NOT EXISTS transfers (
   tx_hash TEXT NOT NULL,
   log_index INTEGER NOT NULL,
+  call_path TEXT,
   block_number INTEGER NOT NULL,
   timestamp TEXT NOT NULL,
+  timestamp_unix INTEGER,
   from_addr TEXT NOT NULL,
   to_addr TEXT NOT NULL,
   token_address TEXT NOT NULL,
@@ -17,6 +20,7 @@ CREATE TABLE IF NOT EXISTS transfers (
 CREATE INDEX IF NOT EXISTS idx_transfers_to ON transfers(to_addr);
 CREATE INDEX IF NOT EXISTS idx_transfers_from ON transfers(from_addr);
 CREATE INDEX IF NOT EXISTS idx_transfers_block ON transfers(block_number);
+CREATE INDEX IF NOT EXISTS idx_transfers_time ON transfers(timestamp_unix);
 
 CREATE TABLE IF NOT EXISTS net_flow (
   exchange TEXT NOT NULL,
@@ -24,6 +28,9 @@ CREATE TABLE IF NOT EXISTS net_flow (
   cumulative_amount_raw TEXT NOT NULL,
   cumulative_amount REAL NOT NULL,
   last_updated TEXT NOT NULL,
+  -- The same sum over finalized blocks only; NULL until the node reports a finalized block.
+  finalized_amount_raw TEXT,
+  finalized_amount REAL,
   PRIMARY KEY (exchange, token_address)
 );
 
@@ -31,3 +38,39 @@ CREATE TABLE IF NOT EXISTS metadata (
   key TEXT PRIMARY KEY,
   value TEXT
 );
+
+CREATE TABLE IF NOT EXISTS blocks (
+  block_number INTEGER PRIMARY KEY,
+  block_hash TEXT NOT NULL,
+  parent_hash TEXT NOT NULL,
+  -- 'pending' at the tip, 'confirmed' once CONFIRMATIONS blocks deep, 'finalized' at or
+  -- below the node's finalized block.
+  status TEXT NOT NULL DEFAULT 'pending'
+);
+
+-- Blocks held back because too few RPC endpoints agreed on them ('hash' or 'logs');
+-- `answers` maps each endpoint to what it reported.
+CREATE TABLE IF NOT EXISTS quarantined_blocks (
+  block_number INTEGER NOT NULL,
+  block_hash TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  answers TEXT NOT NULL,
+  detected_at TEXT NOT NULL,
+  PRIMARY KEY (block_number, block_hash, kind)
+);
+
+-- Per exchange and token inflow/outflow, bucketed by block ('block', bucket_start = block number)
+-- and by time ('hour' / 'day', bucket_start = unix seconds at the start of the bucket).
+CREATE TABLE IF NOT EXISTS net_flow_history (
+  exchange TEXT NOT NULL,
+  token_address TEXT NOT NULL,
+  granularity TEXT NOT NULL,
+  bucket_start INTEGER NOT NULL,
+  inflow_raw TEXT NOT NULL,
+  outflow_raw TEXT NOT NULL,
+  net_raw TEXT NOT NULL,
+  inflow REAL NOT NULL,
+  outflow REAL NOT NULL,
+  net REAL NOT NULL,
+  PRIMARY KEY (exchange, token_address, granularity, bucket_start)
+);
@@ -0,0 +1,170 @@
+use anyhow::{anyhow, bail, Result}; // Error handling
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef}; // Stores addresses in SQLite
+use serde::{Deserialize, Deserializer, Serialize, Serializer}; // Reads addresses from the config
+use std::fmt;
+use std::str::FromStr;
+use tiny_keccak::{Hasher, Keccak}; // For EIP-55 checksums
+
+/// A 20-byte account or contract address. Parsing accepts any letter case, equality
+/// compares bytes, the database stores lowercase hex and `Display` renders the EIP-55
+/// checksummed form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Address(pub [u8; 20]);
+
+impl Address {
+    /// Parses a `0x`-prefixed, 40-digit hex literal at compile time; panics otherwise.
+    pub const fn from_hex_const(hex: &str) -> Self {
+        let hex = hex.as_bytes();
+        assert!(hex.len() == 42 && hex[0] == b'0' && hex[1] == b'x', "address literal must be 0x + 40 hex digits");
+        let mut bytes = [0u8; 20];
+        let mut i = 0;
+        while i < 20 {
+            bytes[i] = (nibble(hex[2 + 2 * i]) << 4) | nibble(hex[3 + 2 * i]);
+            i += 1;
+        }
+        Self(bytes)
+    }
+
+    /// The low 20 bytes of a 32-byte indexed log topic.
+    pub fn from_topic(topic: &str) -> Result<Self> {
+        let hex = topic
+            .strip_prefix("0x")
+            .ok_or_else(|| anyhow!("missing 0x prefix: {}", topic))?;
+        if hex.len() != 64 {
+            bail!("topic is not 32 bytes: {}", topic);
+        }
+        format!("0x{}", &hex[24..]).parse()
+    }
+
+    /// Lowercase `0x` hex, the form stored in the database and sent to the node.
+    pub fn to_lower_hex(self) -> String {
+        let mut out = String::with_capacity(42);
+        out.push_str("0x");
+        for byte in self.0 {
+            out.push_str(&format!("{:02x}", byte));
+        }
+        out
+    }
+
+    /// EIP-55 mixed-case checksum encoding: a hex letter is uppercased when the matching
+    /// nibble of keccak256(lowercase hex) is 8 or more.
+    pub fn to_checksum(self) -> String {
+        let lower = self.to_lower_hex();
+        let mut hash = [0u8; 32];
+        let mut keccak = Keccak::v256();
+        keccak.update(&lower.as_bytes()[2..]);
+        keccak.finalize(&mut hash);
+
+        let mut out = String::with_capacity(42);
+        out.push_str("0x");
+        for (i, c) in lower[2..].chars().enumerate() {
+            let nibble = if i % 2 == 0 { hash[i / 2] >> 4 } else { hash[i / 2] & 0x0f };
+            out.push(if nibble >= 8 { c.to_ascii_uppercase() } else { c });
+        }
+        out
+    }
+}
+
+const fn nibble(c: u8) -> u8 {
+    match c {
+        b'0'..=b'9' => c - b'0',
+        b'a'..=b'f' => c - b'a' + 10,
+        b'A'..=b'F' => c - b'A' + 10,
+        _ => panic!("invalid hex digit in address literal"),
+    }
+}
+
+impl FromStr for Address {
+    type Err = anyhow::Error;
+
+    /// Accepts all-lowercase, all-uppercase or EIP-55 checksummed hex. Mixed case that
+    /// does not match the checksum is rejected, since it usually means a typo.
+    fn from_str(s: &str) -> Result<Self> {
+        let hex = s
+            .strip_prefix("0x")
+            .ok_or_else(|| anyhow!("address {} lacks 0x prefix", s))?;
+        if hex.len() != 40 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            bail!("address {} is not 20 hex bytes", s);
+        }
+        let mut bytes = [0u8; 20];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)?;
+        }
+        let address = Self(bytes);
+        let mixed_case = hex.chars().any(|c| c.is_ascii_lowercase()) && hex.chars().any(|c| c.is_ascii_uppercase());
+        if mixed_case && address.to_checksum() != s {
+            bail!("address {} has an invalid EIP-55 checksum (expected {})", s, address.to_checksum());
+        }
+        Ok(address)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_checksum())
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_lower_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl ToSql for Address {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_lower_hex()))
+    }
+}
+
+impl FromSql for Address {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let s = value.as_str()?;
+        // Stored rows are lowercase; parse case-insensitively so older mixed-case rows still load.
+        s.to_lowercase().parse().map_err(|e: anyhow::Error| FromSqlError::Other(e.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test vectors from EIP-55.
+    const CHECKSUMMED: [&str; 4] = [
+        "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+        "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+        "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+        "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+    ];
+
+    #[test]
+    fn parses_any_case_and_renders_eip55() {
+        for s in CHECKSUMMED {
+            let checksummed: Address = s.parse().unwrap();
+            let lower: Address = s.to_lowercase().parse().unwrap();
+            let upper: Address = format!("0x{}", s[2..].to_uppercase()).parse().unwrap();
+            assert_eq!(checksummed, lower);
+            assert_eq!(checksummed, upper);
+            assert_eq!(checksummed.to_string(), s);
+            assert_eq!(checksummed.to_lower_hex(), s.to_lowercase());
+        }
+        assert_eq!(
+            Address::from_hex_const("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"),
+            CHECKSUMMED[0].parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_bad_checksums_and_lengths() {
+        assert!("0x5aaeb6053F3E94C9b9A09f33669435E7Ef1BeAed".parse::<Address>().is_err());
+        assert!("0x5aaeb6053f3e94c9b9a09f33669435e7ef1bea".parse::<Address>().is_err());
+        assert!("5aaeb6053f3e94c9b9a09f33669435e7ef1beaed".parse::<Address>().is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{anyhow, bail, Result}; // Error handling
+use ethnum::{I256, U256}; // Exact 256-bit token amounts
+
+/// Parses a single 32-byte ABI word holding a uint256, e.g. the data of a Transfer log.
+pub fn parse_word(data: &str) -> Result<U256> {
+    let hex = data
+        .strip_prefix("0x")
+        .ok_or_else(|| anyhow!("missing 0x prefix: {}", data))?;
+    if hex.len() != 64 {
+        bail!("expected one 32-byte word: {}", data);
+    }
+    Ok(U256::from_str_radix(hex, 16)?)
+}
+
+/// Parses a `0x`-prefixed hex quantity such as a transaction `value`.
+pub fn parse_quantity_u256(value: &serde_json::Value) -> Result<U256> {
+    let hex = value
+        .as_str()
+        .and_then(|v| v.strip_prefix("0x"))
+        .ok_or_else(|| anyhow!("expected hex quantity, got {}", value))?;
+    if hex.is_empty() {
+        return Ok(U256::ZERO);
+    }
+    Ok(U256::from_str_radix(hex, 16)?)
+}
+
+/// Parses a decimal `amount_raw` column value.
+pub fn parse_raw(raw: &str) -> Result<U256> {
+    raw.parse().map_err(|e| anyhow!("bad amount_raw {:?}: {}", raw, e))
+}
+
+/// Running signed sum of raw amounts (inflows minus outflows), checked against overflow.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NetAmount(pub I256);
+
+impl NetAmount {
+    /// Parses a stored `cumulative_amount_raw` value.
+    pub fn parse(raw: &str) -> Result<Self> {
+        Ok(Self(raw.parse().map_err(|e| anyhow!("bad cumulative_amount_raw {:?}: {}", raw, e))?))
+    }
+
+    pub fn add(&mut self, amount: U256) -> Result<()> {
+        self.0 = self.0.checked_add_unsigned(amount).ok_or_else(|| anyhow!("net amount overflow"))?;
+        Ok(())
+    }
+
+    pub fn sub(&mut self, amount: U256) -> Result<()> {
+        self.0 = self.0.checked_sub_unsigned(amount).ok_or_else(|| anyhow!("net amount overflow"))?;
+        Ok(())
+    }
+
+    /// Human-readable value scaled down by `decimals`, for the REAL columns.
+    pub fn to_units(self, decimals: u32) -> f64 {
+        let units = format_units(self.0.unsigned_abs(), decimals);
+        let sign = if self.0 < 0 { -1.0 } else { 1.0 };
+        sign * units.parse::<f64>().unwrap_or(f64::NAN)
+    }
+
+    /// Exact signed decimal rendering, e.g. `-2.5`; see `format_units`.
+    pub fn format_units(self, decimals: u32) -> String {
+        let units = format_units(self.0.unsigned_abs(), decimals);
+        if self.0 < 0 { format!("-{}", units) } else { units }
+    }
+}
+
+/// Exact decimal rendering of `raw / 10^decimals`, e.g. `10500000000000000000` with 18
+/// decimals becomes `10.5`. Converting via this string keeps f64 rounding to the last step.
+pub fn format_units(raw: U256, decimals: u32) -> String {
+    let digits = raw.to_string();
+    let decimals = decimals as usize;
+    if decimals == 0 {
+        return digits;
+    }
+    let padded = format!("{:0>width$}", digits, width = decimals + 1);
+    let (int, frac) = padded.split_at(padded.len() - decimals);
+    let frac = frac.trim_end_matches('0');
+    if frac.is_empty() {
+        int.to_string()
+    } else {
+        format!("{}.{}", int, frac)
+    }
+}
+
+/// Human-readable value of a single transfer amount, for the `amount` REAL column.
+pub fn to_units(raw: U256, decimals: u32) -> f64 {
+    format_units(raw, decimals).parse().unwrap_or(f64::NAN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_exact_decimals() {
+        let raw = |s: &str| parse_raw(s).unwrap();
+        assert_eq!(format_units(U256::ZERO, 18), "0");
+        assert_eq!(format_units(raw("10500000000000000000"), 18), "10.5");
+        assert_eq!(format_units(raw("1"), 18), "0.000000000000000001");
+        assert_eq!(format_units(raw("1230"), 6), "0.00123");
+        assert_eq!(format_units(raw("2000000"), 6), "2");
+        assert_eq!(format_units(raw("1500"), 0), "1500");
+        assert_eq!(format_units(U256::MAX, 18), "115792089237316195423570985008687907853269984665640564039457.584007913129639935");
+        assert_eq!(to_units(raw("10500000000000000000"), 18), 10.5);
+    }
+
+    #[test]
+    fn net_amounts_keep_their_sign() {
+        let mut net = NetAmount::default();
+        net.add(parse_raw("1000000000000000000").unwrap()).unwrap();
+        net.sub(parse_raw("3500000000000000000").unwrap()).unwrap();
+        assert_eq!(net.0.to_string(), "-2500000000000000000");
+        assert_eq!(net.format_units(18), "-2.5");
+        assert_eq!(net.to_units(18), -2.5);
+        assert_eq!(NetAmount::parse("-2500000000000000000").unwrap(), net);
+        assert_eq!(NetAmount::default().format_units(18), "0");
+
+        let mut max = NetAmount(I256::MAX);
+        assert!(max.add(U256::ONE).is_err());
+        assert!(NetAmount::parse("1.5").is_err());
+    }
+
+    #[test]
+    fn parses_words_and_quantities() {
+        let word = format!("0x{:064x}", 42);
+        assert_eq!(parse_word(&word).unwrap(), U256::new(42));
+        assert!(parse_word(&word[2..]).is_err());
+        assert!(parse_word(&word[..65]).is_err());
+        assert!(parse_word(&format!("{}00", word)).is_err());
+        assert!(parse_word(&format!("0x{}", "g".repeat(64))).is_err());
+
+        assert_eq!(parse_quantity_u256(&serde_json::json!("0x2a")).unwrap(), U256::new(42));
+        assert_eq!(parse_quantity_u256(&serde_json::json!("0x")).unwrap(), U256::ZERO);
+        assert!(parse_quantity_u256(&serde_json::json!(42)).is_err());
+        assert!(parse_raw("-1").is_err());
+    }
+}
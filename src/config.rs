@@ -0,0 +1,256 @@
+use anyhow::{bail, Context, Result}; // Error handling
+use serde::Deserialize; // Parses the config file
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use crate::address::Address;
+use crate::decode::{LOG_TRANSFER_TOPIC, MRC20_ADDRESS, NATIVE_TOKEN_ADDRESS, TRANSFER_TOPIC};
+
+/// Exchanges and tokens the indexer tracks, loaded from `config.toml` (or a `.json` file).
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    pub exchanges: Vec<Exchange>,
+    pub tokens: Vec<Token>,
+    /// How to find internal native transfers; without it only top-level values are seen.
+    #[serde(default)]
+    pub trace: Option<TraceMode>,
+    /// Cross-check each block against every RPC endpoint before committing it.
+    #[serde(default)]
+    pub quorum: Option<Quorum>,
+}
+
+/// RPC method used to trace the call tree of each block's transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceMode {
+    /// `debug_traceBlockByHash` with the `callTracer` (geth, Bor).
+    CallTracer,
+    /// `trace_block` (Erigon).
+    TraceBlock,
+}
+
+/// How many endpoints must agree on a block before it is indexed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct Quorum {
+    /// Endpoints that must report the same block hash, counting the one it was fetched from.
+    pub agree: usize,
+    /// Also require them to return the same tracked logs for that hash.
+    #[serde(default)]
+    pub check_logs: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Exchange {
+    pub name: String,
+    pub wallets: Vec<Wallet>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Wallet {
+    pub address: Address,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub kind: Option<WalletKind>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WalletKind {
+    Hot,
+    Cold,
+    Deposit,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Token {
+    pub symbol: String,
+    pub address: Address,
+    pub decimals: u32,
+}
+
+impl Config {
+    /// Reads and validates the config; the format is picked from the file extension.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading config {}", path.display()))?;
+        let config: Config = match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::from_str(&text)?,
+            _ => toml::from_str(&text)?,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.exchanges.is_empty() || self.tokens.is_empty() {
+            bail!("config needs at least one exchange and one token");
+        }
+        if self.quorum.is_some_and(|q| q.agree == 0) {
+            bail!("quorum.agree must be at least 1");
+        }
+        let mut owners = HashMap::new();
+        for exchange in &self.exchanges {
+            if exchange.wallets.is_empty() {
+                bail!("exchange {} has no wallets", exchange.name);
+            }
+            for wallet in &exchange.wallets {
+                if let Some(other) = owners.insert(wallet.address, &exchange.name)
+                    && other != &exchange.name
+                {
+                    bail!("wallet {} is listed under both {} and {}", wallet.address, other, exchange.name);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Every wallet of every exchange, used to filter transfers at ingestion.
+    pub fn tracked_addresses(&self) -> Vec<Address> {
+        self.exchanges
+            .iter()
+            .flat_map(|e| e.wallets.iter().map(|w| w.address))
+            .collect()
+    }
+
+    /// Whether native POL is tracked, i.e. the native marker is listed as a token.
+    pub fn tracks_native(&self) -> bool {
+        self.token(NATIVE_TOKEN_ADDRESS).is_some()
+    }
+
+    /// The trace mode to use, if any: tracing only matters when native POL is tracked.
+    pub fn native_trace(&self) -> Option<TraceMode> {
+        self.trace.filter(|_| self.tracks_native())
+    }
+
+    /// Contract addresses and first topics to request from `eth_getLogs`: `Transfer` for
+    /// every ERC-20 token, plus the MRC20 `LogTransfer` when native POL is tracked.
+    pub fn log_filter(&self) -> (Vec<Address>, Vec<&str>) {
+        let mut addresses: Vec<Address> = self
+            .tokens
+            .iter()
+            .map(|t| t.address)
+            .filter(|a| *a != NATIVE_TOKEN_ADDRESS)
+            .collect();
+        let mut topics = Vec::new();
+        if !addresses.is_empty() {
+            topics.push(TRANSFER_TOPIC);
+        }
+        if self.tracks_native() {
+            if !addresses.contains(&MRC20_ADDRESS) {
+                addresses.push(MRC20_ADDRESS);
+            }
+            topics.push(LOG_TRANSFER_TOPIC);
+        }
+        (addresses, topics)
+    }
+
+    pub fn token(&self, address: Address) -> Option<&Token> {
+        self.tokens.iter().find(|t| t.address == address)
+    }
+}
+
+impl fmt::Display for Wallet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.address)?;
+        if let Some(kind) = self.kind {
+            write!(f, " [{:?}]", kind)?;
+        }
+        if let Some(label) = &self.label {
+            write!(f, " {}", label)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOML: &str = r#"
+        trace = "trace_block"
+
+        [quorum]
+        agree = 2
+
+        [[exchanges]]
+        name = "Binance"
+        wallets = [
+          { address = "0xF977814e90dA44bFA03b6295A0616a897441aceC", kind = "hot", label = "Binance 8" },
+          { address = "0xe7804c37c13166ff0b37f5ae0bb07a3aebb6e245" },
+        ]
+
+        [[tokens]]
+        symbol = "POL"
+        address = "0xeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee"
+        decimals = 18
+    "#;
+
+    fn parse(toml: &str) -> Result<Config> {
+        let config: Config = toml::from_str(toml)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    #[test]
+    fn loads_toml_and_json_by_extension() {
+        let config = Config::load("config.toml").unwrap();
+        assert_eq!(config.exchanges[0].name, "Binance");
+        assert!(config.tracks_native());
+
+        let dir = std::env::temp_dir();
+        let toml_path = dir.join(format!("indexer-config-{}.toml", std::process::id()));
+        let json_path = dir.join(format!("indexer-config-{}.json", std::process::id()));
+        std::fs::write(&toml_path, TOML).unwrap();
+        let json = serde_json::json!({
+            "exchanges": [{ "name": "Binance", "wallets": [{ "address": "0xf977814e90da44bfa03b6295a0616a897441acec" }] }],
+            "tokens": [{ "symbol": "POL", "address": "0xeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee", "decimals": 18 }],
+        });
+        std::fs::write(&json_path, json.to_string()).unwrap();
+        let from_toml = Config::load(&toml_path);
+        let from_json = Config::load(&json_path);
+        std::fs::remove_file(&toml_path).unwrap();
+        std::fs::remove_file(&json_path).unwrap();
+
+        let from_toml = from_toml.unwrap();
+        assert_eq!(from_toml.native_trace(), Some(TraceMode::TraceBlock));
+        assert_eq!(from_toml.quorum, Some(Quorum { agree: 2, check_logs: false }));
+        let wallet = &from_toml.exchanges[0].wallets[0];
+        assert_eq!(wallet.to_string(), "0xF977814e90dA44bFA03b6295A0616a897441aceC [Hot] Binance 8");
+        assert_eq!(from_toml.tracked_addresses().len(), 2);
+
+        let from_json = from_json.unwrap();
+        assert_eq!(from_json.tracked_addresses(), vec![wallet.address]);
+        assert!(from_json.trace.is_none() && from_json.quorum.is_none());
+
+        assert!(Config::load(dir.join("no-such-indexer-config.toml")).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_configs() {
+        let err = |toml: &str| parse(toml).unwrap_err().to_string();
+        assert!(parse(TOML).is_ok());
+
+        assert!(err("exchanges = []\ntokens = []").contains("at least one exchange"));
+        let no_tokens = format!("tokens = []\n{}", TOML.split("[[tokens]]").next().unwrap());
+        assert!(err(&no_tokens).contains("at least one exchange and one token"));
+        let no_wallets = TOML.replace("name = \"Binance\"", "name = \"Kraken\"\nwallets = []\n[[exchanges]]\nname = \"Binance\"");
+        assert!(err(&no_wallets).contains("exchange Kraken has no wallets"));
+        assert!(err(&TOML.replace("agree = 2", "agree = 0")).contains("quorum.agree"));
+        assert!(err(&TOML.replace(
+            "{ address = \"0xe7804c37c13166ff0b37f5ae0bb07a3aebb6e245\" },",
+            "{ address = \"0xe7804c37c13166ff0b37f5ae0bb07a3aebb6e245\" },
+            ]
+
+            [[exchanges]]
+            name = \"Coinbase\"
+            wallets = [
+              { address = \"0xE7804C37C13166FF0B37F5AE0BB07A3AEBB6E245\" },"
+        ))
+        .contains("listed under both Binance and Coinbase"));
+        // One wrong-case letter breaks the EIP-55 checksum.
+        assert!(parse(&TOML.replace("0xF977814e90dA44bFA03b6295A0616a897441aceC", "0xf977814e90dA44bFA03b6295A0616a897441aceC")).is_err());
+        assert!(parse(&TOML.replace("kind = \"hot\"", "kind = \"warm\"")).is_err());
+    }
+}
@@ -0,0 +1,280 @@
+use anyhow::{anyhow, Result}; // Error handling
+use chrono::{DateTime, SecondsFormat, Utc}; // For block timestamps
+use rusqlite::types::{ToSqlOutput, ValueRef};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, ToSql}; // Connects to SQLite database
+use std::ops::{Deref, DerefMut};
+
+use crate::migrations::{self, Migration};
+use crate::rpc::BlockHeader;
+use crate::sql::{Sql, SqlValue};
+
+/// Brings the database up to the latest schema by applying the pending `migrations::SQLITE`
+/// in order, and returns them. With `dry_run` nothing is changed. Fails on a database
+/// migrated by a newer build.
+pub fn migrate(conn: &mut Connection, dry_run: bool) -> Result<Vec<&'static Migration>> {
+    let current = schema_version(conn)?;
+    let pending = migrations::pending(migrations::SQLITE, current)?;
+    if dry_run || pending.is_empty() {
+        return Ok(pending.iter().collect());
+    }
+    if current == 0 {
+        upgrade_unversioned(conn)?;
+    }
+    for migration in pending {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration.sql)?;
+        tx.execute(
+            "INSERT INTO metadata (key, value) VALUES (?1, ?2)
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            params![SCHEMA_VERSION, migration.version.to_string()],
+        )?;
+        tx.commit()?;
+    }
+    Ok(pending.iter().collect())
+}
+
+/// Version of the last migration applied, 0 for an empty database or one created before
+/// migrations were versioned.
+pub fn schema_version(conn: &Connection) -> Result<u32> {
+    if !table_exists(conn, "metadata")? {
+        return Ok(0);
+    }
+    let value: Option<String> = conn
+        .query_row("SELECT value FROM metadata WHERE key = ?1", [SCHEMA_VERSION], |row| row.get(0))
+        .optional()?;
+    Ok(value.map(|v| v.parse()).transpose()?.unwrap_or(0))
+}
+
+/// Adds the columns that unversioned databases may lack, so that migration 1 (whose
+/// tables already exist there) leaves them in the same shape as a fresh database.
+fn upgrade_unversioned(conn: &Connection) -> Result<()> {
+    // Databases created before `timestamp_unix` existed need the column before the index on it.
+    if table_exists(conn, "transfers")? && !column_exists(conn, "transfers", "timestamp_unix")? {
+        conn.execute_batch(
+            "ALTER TABLE transfers ADD COLUMN timestamp_unix INTEGER;
+             UPDATE transfers SET timestamp_unix = CAST(strftime('%s', timestamp) AS INTEGER);",
+        )?;
+    }
+    if table_exists(conn, "transfers")? && !column_exists(conn, "transfers", "call_path")? {
+        conn.execute_batch("ALTER TABLE transfers ADD COLUMN call_path TEXT;")?;
+    }
+    if table_exists(conn, "blocks")? && !column_exists(conn, "blocks", "status")? {
+        conn.execute_batch("ALTER TABLE blocks ADD COLUMN status TEXT NOT NULL DEFAULT 'pending';")?;
+    }
+    if table_exists(conn, "net_flow")? && !column_exists(conn, "net_flow", "finalized_amount_raw")? {
+        conn.execute_batch(
+            "ALTER TABLE net_flow ADD COLUMN finalized_amount_raw TEXT;
+             ALTER TABLE net_flow ADD COLUMN finalized_amount REAL;",
+        )?;
+    }
+    Ok(())
+}
+
+fn table_exists(conn: &Connection, table: &str) -> Result<bool> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+        [table],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+fn column_exists(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let names = stmt.query_map([], |row| row.get::<_, String>(1))?;
+    for name in names {
+        if name? == column {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// `metadata` key holding the height of the last fully indexed block.
+pub const LAST_INDEXED_BLOCK: &str = "last_indexed_block";
+/// `metadata` key holding the newest block the node reported as finalized.
+pub const FINALIZED_BLOCK: &str = "finalized_block";
+/// `metadata` key holding the version of the last schema migration applied.
+pub const SCHEMA_VERSION: &str = "schema_version";
+
+/// Reads the follower checkpoint, `None` on a fresh database.
+pub fn last_indexed_block(conn: &Connection) -> Result<Option<u64>> {
+    block_metadata(conn, LAST_INDEXED_BLOCK)
+}
+
+pub fn set_last_indexed_block(conn: &Connection, block: u64) -> Result<()> {
+    set_block_metadata(conn, LAST_INDEXED_BLOCK, block)
+}
+
+/// Newest finalized block seen so far, `None` until the node first reports one.
+pub fn finalized_block(conn: &Connection) -> Result<Option<u64>> {
+    block_metadata(conn, FINALIZED_BLOCK)
+}
+
+fn block_metadata(conn: &Connection, key: &str) -> Result<Option<u64>> {
+    let value: Option<String> = conn
+        .query_row("SELECT value FROM metadata WHERE key = ?1", [key], |row| row.get(0))
+        .optional()?;
+    Ok(value.map(|v| v.parse()).transpose()?)
+}
+
+fn set_block_metadata(conn: &Connection, key: &str, block: u64) -> Result<()> {
+    conn.execute(
+        "INSERT INTO metadata (key, value) VALUES (?1, ?2)
+         ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        params![key, block.to_string()],
+    )?;
+    Ok(())
+}
+
+/// Marks blocks at or below `confirmed` as confirmed and those at or below `finalized` as
+/// finalized, and records `finalized` as the finality checkpoint. Statuses only move forward.
+pub fn update_block_statuses(conn: &Connection, confirmed: u64, finalized: Option<u64>) -> Result<()> {
+    conn.execute(
+        "UPDATE blocks SET status = 'confirmed' WHERE block_number <= ?1 AND status = 'pending'",
+        [confirmed as i64],
+    )?;
+    if let Some(finalized) = finalized {
+        conn.execute(
+            "UPDATE blocks SET status = 'finalized' WHERE block_number <= ?1 AND status <> 'finalized'",
+            [finalized as i64],
+        )?;
+        set_block_metadata(conn, FINALIZED_BLOCK, finalized)?;
+    }
+    Ok(())
+}
+
+/// Records the hash of an indexed block so the next block's parent hash can be checked.
+pub fn insert_block(conn: &Connection, header: &BlockHeader) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO blocks (block_number, block_hash, parent_hash) VALUES (?1, ?2, ?3)",
+        params![header.number as i64, header.hash, header.parent_hash],
+    )?;
+    Ok(())
+}
+
+/// Hash stored for an indexed block, `None` if that height was never indexed.
+pub fn block_hash(conn: &Connection, number: u64) -> Result<Option<String>> {
+    Ok(conn
+        .query_row("SELECT block_hash FROM blocks WHERE block_number = ?1", [number as i64], |row| row.get(0))
+        .optional()?)
+}
+
+/// Drops every block and transfer above `ancestor` and moves the checkpoint back to it.
+/// Returns the number of orphaned transfers deleted.
+pub fn rollback_to(conn: &Connection, ancestor: u64) -> Result<usize> {
+    let deleted = conn.execute("DELETE FROM transfers WHERE block_number > ?1", [ancestor as i64])?;
+    conn.execute("DELETE FROM blocks WHERE block_number > ?1", [ancestor as i64])?;
+    set_last_indexed_block(conn, ancestor)?;
+    Ok(deleted)
+}
+
+/// Records that `block_hash` at `number` failed the `kind` quorum check; a repeat of the same
+/// failure refreshes the answers and time.
+pub fn quarantine_block(conn: &Connection, number: u64, block_hash: &str, kind: &str, answers: &str) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO quarantined_blocks (block_number, block_hash, kind, answers, detected_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![number as i64, block_hash, kind, answers, iso_timestamp(Utc::now().timestamp())?],
+    )?;
+    Ok(())
+}
+
+/// Formats unix seconds as ISO-8601 UTC, e.g. `2025-09-28T12:00:00Z`.
+pub fn iso_timestamp(unix: i64) -> Result<String> {
+    let time = DateTime::from_timestamp(unix, 0).ok_or_else(|| anyhow!("timestamp out of range: {}", unix))?;
+    Ok(time.to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+/// `BEGIN` .. `COMMIT` on a mutably borrowed connection, rolled back when dropped
+/// uncommitted. Unlike `rusqlite::Transaction`, which only holds a shared reference, it is
+/// `Send`, so it can be held across the `.await`s of the shared `Sql` code.
+pub struct Tx<'a> {
+    conn: &'a mut Connection,
+    committed: bool,
+}
+
+impl<'a> Tx<'a> {
+    pub fn begin(conn: &'a mut Connection) -> Result<Self> {
+        conn.execute_batch("BEGIN")?;
+        Ok(Self { conn, committed: false })
+    }
+
+    pub fn commit(mut self) -> Result<()> {
+        self.conn.execute_batch("COMMIT")?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for Tx<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = self.conn.execute_batch("ROLLBACK");
+        }
+    }
+}
+
+impl Deref for Tx<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn
+    }
+}
+
+impl DerefMut for Tx<'_> {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.conn
+    }
+}
+
+impl ToSql for SqlValue {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match self {
+            SqlValue::Null => ToSqlOutput::Borrowed(ValueRef::Null),
+            SqlValue::Integer(n) => ToSqlOutput::from(*n),
+            SqlValue::Real(x) => ToSqlOutput::from(*x),
+            SqlValue::Text(text) => ToSqlOutput::from(text.as_str()),
+        })
+    }
+}
+
+impl Sql for Connection {
+    async fn execute(&mut self, sql: &str, params: &[SqlValue]) -> Result<u64> {
+        Ok(Connection::execute(self, sql, params_from_iter(params))? as u64)
+    }
+
+    async fn query(&mut self, sql: &str, params: &[SqlValue]) -> Result<Vec<Vec<SqlValue>>> {
+        let mut stmt = self.prepare(sql)?;
+        let columns = stmt.column_count();
+        let mut rows = stmt.query(params_from_iter(params))?;
+        let mut result = Vec::new();
+        while let Some(row) = rows.next()? {
+            let mut values = Vec::with_capacity(columns);
+            for i in 0..columns {
+                values.push(match row.get_ref(i)? {
+                    ValueRef::Null => SqlValue::Null,
+                    ValueRef::Integer(n) => SqlValue::Integer(n),
+                    ValueRef::Real(x) => SqlValue::Real(x),
+                    ValueRef::Text(text) => SqlValue::Text(String::from_utf8(text.to_vec())?),
+                    ValueRef::Blob(_) => return Err(anyhow!("unexpected BLOB in column {} of {}", i, sql)),
+                });
+            }
+            result.push(values);
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_iso_timestamps() {
+        assert_eq!(iso_timestamp(0).unwrap(), "1970-01-01T00:00:00Z");
+        assert_eq!(iso_timestamp(1_758_371_696).unwrap(), "2025-09-20T12:34:56Z");
+        assert!(iso_timestamp(i64::MAX).is_err());
+    }
+}
@@ -0,0 +1,283 @@
+use anyhow::{anyhow, bail, Result}; // Error handling
+use ethnum::U256; // Exact 256-bit token amounts
+use serde_json::Value; // Parses JSON
+use std::collections::HashMap;
+
+use crate::address::Address;
+use crate::amount;
+use crate::rpc::parse_quantity;
+
+/// keccak256("Transfer(address,address,uint256)")
+pub const TRANSFER_TOPIC: &str = "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+
+/// keccak256("LogTransfer(address,address,address,uint256,uint256,uint256,uint256,uint256)"),
+/// emitted by the MRC20 system contract for every native value transfer on Polygon PoS.
+pub const LOG_TRANSFER_TOPIC: &str = "0xe6497e3ee548a3372136af2fcb0696db31fc6cf20260707645068bd3fe97f3c4";
+
+/// MRC20 system contract that emits `LogTransfer`.
+pub const MRC20_ADDRESS: Address = Address::from_hex_const("0x0000000000000000000000000000000000001010");
+
+/// Marker stored in `token_address` (and listed in the config) for native POL.
+pub const NATIVE_TOKEN_ADDRESS: Address = Address::from_hex_const("0xeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee");
+
+/// `log_index` of a transaction's top-level value, which has no log of its own. Internal
+/// calls found by trace mode continue downwards from here (see `trace::call_log_index`).
+pub const TX_VALUE_LOG_INDEX: i64 = -1;
+
+/// One decoded token movement: an ERC-20 `Transfer` log or a native POL transfer.
+#[derive(Debug, Clone)]
+pub struct Transfer {
+    pub tx_hash: String,
+    pub log_index: i64,
+    /// Position in the transaction's call tree for native transfers taken from the
+    /// transaction itself or a trace: `0` is the top-level call, `0.2.1` the second
+    /// sub-call of its third sub-call. `None` for transfers decoded from logs.
+    pub call_path: Option<String>,
+    pub block_number: u64,
+    pub token_address: Address,
+    pub from: Address,
+    pub to: Address,
+    pub amount_raw: U256,
+    /// Outcome of the transaction, once its receipt has been fetched.
+    pub receipt: Option<Receipt>,
+}
+
+/// The parts of a transaction receipt stored alongside its transfers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Receipt {
+    /// `status` 1; a failed transaction's transfers did not happen.
+    pub success: bool,
+    /// `gasUsed * effectiveGasPrice` in wei, `None` if the node omits the gas price.
+    pub fee_raw: Option<U256>,
+}
+
+impl Transfer {
+    /// Amount scaled down by the token's decimals for the `amount` REAL column.
+    pub fn amount(&self, decimals: u32) -> f64 {
+        amount::to_units(self.amount_raw, decimals)
+    }
+
+    /// Whether the transfer affects net-flow: all but those of failed transactions.
+    /// Without a receipt the transaction is assumed to have succeeded.
+    pub fn counts_towards_net_flow(&self) -> bool {
+        self.receipt.is_none_or(|r| r.success)
+    }
+}
+
+/// Decodes an `eth_getLogs` entry of either kind; `None` for logs with another topic.
+/// `Transfer` logs without exactly three topics (ERC-721 and non-standard tokens share the
+/// signature but index the value too) are skipped rather than failing the block.
+pub fn decode_log(log: &Value) -> Result<Option<Transfer>> {
+    match log["topics"][0].as_str() {
+        Some(TRANSFER_TOPIC) if log["topics"].as_array().map_or(0, Vec::len) != 3 => {
+            println!(
+                "Skipping Transfer log {} of tx {} from {}: not an ERC-20 Transfer",
+                log["logIndex"], log["transactionHash"], log["address"]
+            );
+            Ok(None)
+        }
+        Some(TRANSFER_TOPIC) => decode_transfer_log(log).map(Some),
+        Some(LOG_TRANSFER_TOPIC) if address_field(log, "address")? == MRC20_ADDRESS => decode_log_transfer(log).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Decodes an `eth_getLogs` entry: `from`/`to` come from topics 1 and 2, the value from data.
+pub fn decode_transfer_log(log: &Value) -> Result<Transfer> {
+    let topics = log["topics"]
+        .as_array()
+        .ok_or_else(|| anyhow!("log without topics: {}", log))?;
+    if topics.len() != 3 || topics[0].as_str() != Some(TRANSFER_TOPIC) {
+        bail!("not an ERC-20 Transfer log: {}", log);
+    }
+    let data = log["data"]
+        .as_str()
+        .ok_or_else(|| anyhow!("log without data: {}", log))?;
+
+    Ok(Transfer {
+        tx_hash: hex_field(log, "transactionHash")?,
+        log_index: parse_quantity(&log["logIndex"])? as i64,
+        call_path: None,
+        block_number: parse_quantity(&log["blockNumber"])?,
+        token_address: address_field(log, "address")?,
+        from: topic_address(&topics[1])?,
+        to: topic_address(&topics[2])?,
+        amount_raw: amount::parse_word(data)?,
+        receipt: None,
+    })
+}
+
+/// Decodes an MRC20 `LogTransfer(token, from, to, amount, input1, input2, output1, output2)`:
+/// `from`/`to` are topics 2 and 3, the amount is the first data word.
+pub fn decode_log_transfer(log: &Value) -> Result<Transfer> {
+    let topics = log["topics"]
+        .as_array()
+        .ok_or_else(|| anyhow!("log without topics: {}", log))?;
+    if topics.len() != 4 || topics[0].as_str() != Some(LOG_TRANSFER_TOPIC) {
+        bail!("not an MRC20 LogTransfer log: {}", log);
+    }
+    let data = log["data"]
+        .as_str()
+        .ok_or_else(|| anyhow!("log without data: {}", log))?;
+    if data.len() != 2 + 5 * 64 {
+        bail!("LogTransfer data is not five 32-byte words: {}", data);
+    }
+
+    Ok(Transfer {
+        tx_hash: hex_field(log, "transactionHash")?,
+        log_index: parse_quantity(&log["logIndex"])? as i64,
+        call_path: None,
+        block_number: parse_quantity(&log["blockNumber"])?,
+        token_address: NATIVE_TOKEN_ADDRESS,
+        from: topic_address(&topics[2])?,
+        to: topic_address(&topics[3])?,
+        amount_raw: amount::parse_word(&data[..66])?,
+        receipt: None,
+    })
+}
+
+/// Native transfers carried as the top-level `value` of a block's transactions
+/// (`eth_getBlockByNumber` with full transaction objects). Contract creations and
+/// zero-value transactions are skipped.
+pub fn decode_tx_values(block_number: u64, transactions: &[Value]) -> Result<Vec<Transfer>> {
+    let mut transfers = Vec::new();
+    for tx in transactions {
+        let value = amount::parse_quantity_u256(&tx["value"])?;
+        if tx["to"].is_null() {
+            continue;
+        }
+        if value == U256::ZERO {
+            continue;
+        }
+        transfers.push(Transfer {
+            tx_hash: hex_field(tx, "hash")?,
+            log_index: TX_VALUE_LOG_INDEX,
+            call_path: Some("0".to_string()),
+            block_number,
+            token_address: NATIVE_TOKEN_ADDRESS,
+            from: address_field(tx, "from")?,
+            to: address_field(tx, "to")?,
+            amount_raw: value,
+            receipt: None,
+        });
+    }
+    Ok(transfers)
+}
+
+/// Decodes `eth_getBlockReceipts` / `eth_getTransactionReceipt` results, keyed by
+/// lowercase transaction hash.
+pub fn decode_receipts(receipts: &[Value]) -> Result<HashMap<String, Receipt>> {
+    let mut decoded = HashMap::with_capacity(receipts.len());
+    for receipt in receipts {
+        let gas_price = &receipt["effectiveGasPrice"];
+        let fee_raw = if gas_price.is_null() {
+            None
+        } else {
+            let gas_used = amount::parse_quantity_u256(&receipt["gasUsed"])?;
+            let fee = gas_used
+                .checked_mul(amount::parse_quantity_u256(gas_price)?)
+                .ok_or_else(|| anyhow!("fee overflow in receipt: {}", receipt))?;
+            Some(fee)
+        };
+        let success = parse_quantity(&receipt["status"])? == 1;
+        decoded.insert(hex_field(receipt, "transactionHash")?, Receipt { success, fee_raw });
+    }
+    Ok(decoded)
+}
+
+fn hex_field(log: &Value, key: &str) -> Result<String> {
+    log[key]
+        .as_str()
+        .map(str::to_lowercase)
+        .ok_or_else(|| anyhow!("log without {}: {}", key, log))
+}
+
+fn address_field(log: &Value, key: &str) -> Result<Address> {
+    log[key]
+        .as_str()
+        .ok_or_else(|| anyhow!("log without {}: {}", key, log))?
+        .parse()
+}
+
+/// An indexed address is left-padded to 32 bytes; keep the low 20.
+fn topic_address(topic: &Value) -> Result<Address> {
+    Address::from_topic(topic.as_str().ok_or_else(|| anyhow!("bad topic: {}", topic))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const TOKEN: &str = "0x455e53cbb86018ac2b8092fdcd39d8444affc3f6";
+    const FROM_TOPIC: &str = "0x000000000000000000000000f977814e90da44bfa03b6295a0616a897441acec";
+    const TO_TOPIC: &str = "0x00000000000000000000000000000000000000000000000000000000000000bb";
+    // 10.5 tokens with 18 decimals.
+    const AMOUNT_WORD: &str = "0x00000000000000000000000000000000000000000000000091b77e5e5d9a0000";
+
+    fn transfer_log(topics: Vec<&str>, data: &str) -> Value {
+        json!({
+            "address": TOKEN,
+            "topics": topics,
+            "data": data,
+            "blockNumber": "0x64",
+            "transactionHash": "0xABC1",
+            "logIndex": "0x3",
+        })
+    }
+
+    #[test]
+    fn decodes_erc20_transfer_logs() {
+        let log = transfer_log(vec![TRANSFER_TOPIC, FROM_TOPIC, TO_TOPIC], AMOUNT_WORD);
+        let transfer = decode_log(&log).unwrap().unwrap();
+        assert_eq!(transfer.tx_hash, "0xabc1");
+        assert_eq!(transfer.log_index, 3);
+        assert_eq!(transfer.block_number, 100);
+        assert_eq!(transfer.token_address, TOKEN.parse().unwrap());
+        assert_eq!(transfer.from.to_lower_hex(), "0xf977814e90da44bfa03b6295a0616a897441acec");
+        assert_eq!(transfer.to.to_lower_hex(), "0x00000000000000000000000000000000000000bb");
+        assert_eq!(transfer.amount_raw.to_string(), "10500000000000000000");
+        assert!(transfer.call_path.is_none());
+    }
+
+    #[test]
+    fn skips_transfer_logs_that_are_not_erc20() {
+        // ERC-721 indexes the token id as a fourth topic and has no data.
+        let nft = transfer_log(vec![TRANSFER_TOPIC, FROM_TOPIC, TO_TOPIC, AMOUNT_WORD], "0x");
+        assert!(decode_log(&nft).unwrap().is_none());
+        assert!(decode_transfer_log(&nft).is_err());
+
+        // Approval(address,address,uint256)
+        let approval = "0x8c5be1e5ebec7d5bd14f71427d1e84f3dd0314c0f7b2291e5b200ac8c7c3b925";
+        let other = transfer_log(vec![approval, FROM_TOPIC, TO_TOPIC], AMOUNT_WORD);
+        assert!(decode_log(&other).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_transfer_logs() {
+        let short = transfer_log(vec![TRANSFER_TOPIC, FROM_TOPIC, TO_TOPIC], "0x01");
+        assert!(decode_log(&short).is_err());
+        let mut no_hash = transfer_log(vec![TRANSFER_TOPIC, FROM_TOPIC, TO_TOPIC], AMOUNT_WORD);
+        no_hash.as_object_mut().unwrap().remove("transactionHash");
+        assert!(decode_log(&no_hash).is_err());
+    }
+
+    #[test]
+    fn decodes_mrc20_log_transfers_only_from_the_system_contract() {
+        let token_topic = "0x0000000000000000000000000000000000000000000000000000000000001010";
+        let data = format!("{}{}", AMOUNT_WORD, "0".repeat(4 * 64));
+        let mut log = transfer_log(vec![LOG_TRANSFER_TOPIC, token_topic, FROM_TOPIC, TO_TOPIC], &data);
+        assert!(decode_log(&log).unwrap().is_none());
+
+        log["address"] = json!(MRC20_ADDRESS.to_lower_hex());
+        let transfer = decode_log(&log).unwrap().unwrap();
+        assert_eq!(transfer.token_address, NATIVE_TOKEN_ADDRESS);
+        assert_eq!(transfer.from.to_lower_hex(), "0xf977814e90da44bfa03b6295a0616a897441acec");
+        assert_eq!(transfer.amount_raw.to_string(), "10500000000000000000");
+
+        log["data"] = json!(AMOUNT_WORD);
+        assert!(decode_log_transfer(&log).is_err());
+        let three_topics = transfer_log(vec![LOG_TRANSFER_TOPIC, FROM_TOPIC, TO_TOPIC], &data);
+        assert!(decode_log_transfer(&three_topics).is_err());
+    }
+}
@@ -0,0 +1,80 @@
+use anyhow::{Context, Result}; // Error handling
+use serde::Deserialize; // Parses fixture files
+use std::path::Path;
+
+use crate::address::Address;
+use crate::amount;
+use crate::config::Config;
+use crate::decode::Transfer;
+use crate::storage::Storage;
+
+/// Fixture shipped with the repository: six made-up native POL transfers, four of them
+/// touching Binance wallets. Never load it into a database holding real data.
+pub const SAMPLE_TRANSFERS: &str = "fixtures/sample_transfers.json";
+
+/// One transfer of a fixture file.
+#[derive(Deserialize)]
+struct FixtureTransfer {
+    tx_hash: String,
+    log_index: i64,
+    block_number: u64,
+    timestamp_unix: i64,
+    from: Address,
+    to: Address,
+    token_address: Address,
+    amount_raw: String,
+}
+
+/// Reads a JSON array of transfers as `(transfer, decimals, block time)` rows for
+/// `Storage::store_transfers`. Decimals come from the config, 18 for untracked tokens.
+pub fn read(path: impl AsRef<Path>, config: &Config) -> Result<Vec<(Transfer, u32, i64)>> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    let rows: Vec<FixtureTransfer> = serde_json::from_str(&text).with_context(|| format!("parsing {}", path.display()))?;
+    rows.into_iter()
+        .map(|row| {
+            let decimals = config.token(row.token_address).map_or(18, |t| t.decimals);
+            let transfer = Transfer {
+                tx_hash: row.tx_hash,
+                log_index: row.log_index,
+                call_path: None,
+                block_number: row.block_number,
+                token_address: row.token_address,
+                from: row.from,
+                to: row.to,
+                amount_raw: amount::parse_raw(&row.amount_raw)?,
+                receipt: None,
+            };
+            Ok((transfer, decimals, row.timestamp_unix))
+        })
+        .collect()
+}
+
+/// Stores the fixture file at `path` in `store`. Its transfers count towards net-flow like
+/// indexed ones; loading the same file twice adds nothing. Returns the number of rows read.
+pub async fn load<S: Storage>(store: &mut S, config: &Config, path: impl AsRef<Path>) -> Result<usize> {
+    let rows = read(path, config)?;
+    store.store_transfers(config, &rows).await?;
+    Ok(rows.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::memory_db;
+
+    #[tokio::test]
+    async fn loads_the_sample_fixture_once() {
+        let config = Config::load("config.toml").unwrap();
+        let mut conn = memory_db();
+        assert_eq!(load(&mut conn, &config, SAMPLE_TRANSFERS).await.unwrap(), 6);
+        load(&mut conn, &config, SAMPLE_TRANSFERS).await.unwrap();
+
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM transfers", [], |row| row.get(0)).unwrap();
+        assert_eq!(count, 6);
+        let flows = conn.net_flows().await.unwrap();
+        assert_eq!(flows.len(), 1);
+        // 10.5 + 100 + 50 in, 2 + 0.001 out.
+        assert_eq!(flows[0].cumulative_raw.0.to_string(), "158499000000000000000");
+    }
+}
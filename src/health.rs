@@ -0,0 +1,155 @@
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::ratelimit::TokenBucket;
+use crate::rpc::RpcError;
+
+/// Weight of the newest observation in the smoothed latency and error rate.
+const SMOOTHING: f64 = 0.2;
+/// An endpoint whose head trails the best known head by more than this is skipped.
+pub const MAX_LAG_BLOCKS: u64 = 5;
+/// Cooldown after one failure; doubles with each consecutive failure up to `MAX_COOLDOWN`.
+const BASE_COOLDOWN: Duration = Duration::from_secs(5);
+const MAX_COOLDOWN: Duration = Duration::from_secs(300);
+
+/// One RPC endpoint and what the client has observed of it.
+pub struct Endpoint {
+    pub url: String,
+    pub limiter: Option<TokenBucket>,
+    health: Mutex<Health>,
+}
+
+/// Observed health of an endpoint.
+#[derive(Debug, Clone, Default)]
+pub struct Health {
+    /// Smoothed response time in milliseconds, `None` before the first response.
+    pub latency_ms: Option<f64>,
+    /// Smoothed share of failed requests, from 0 to 1.
+    pub error_rate: f64,
+    /// Latest block height it reported.
+    pub head: Option<u64>,
+    consecutive_failures: u32,
+    down_until: Option<Instant>,
+}
+
+impl Health {
+    fn is_down(&self, now: Instant) -> bool {
+        self.down_until.is_some_and(|until| until > now)
+    }
+
+    /// Whether it last reported a head below `block`, so it cannot serve that block yet.
+    /// An endpoint whose head is unknown is given the benefit of the doubt.
+    fn is_behind(&self, block: Option<u64>) -> bool {
+        matches!((self.head, block), (Some(head), Some(block)) if head < block)
+    }
+
+    /// Lower is better: latency, heavily penalized by the error rate. Endpoints that have
+    /// not answered yet score 0 so they get probed.
+    fn score(&self) -> f64 {
+        self.latency_ms.unwrap_or(0.0) + self.error_rate * 1_000.0
+    }
+}
+
+impl Endpoint {
+    pub fn new(url: String) -> Self {
+        Self { url, limiter: None, health: Mutex::new(Health::default()) }
+    }
+
+    /// Host of the URL, for logs.
+    pub fn name(&self) -> &str {
+        host(&self.url)
+    }
+
+    pub fn health(&self) -> Health {
+        self.health.lock().unwrap().clone()
+    }
+
+    /// Folds the outcome of one request that took `latency` into the endpoint's health.
+    /// Only failures worth retrying count against it: a JSON-RPC error for a bad request
+    /// says nothing about the node.
+    pub fn record<T>(&self, latency: Duration, outcome: &Result<T, RpcError>) {
+        let mut health = self.health.lock().unwrap();
+        match outcome {
+            Err(err) if err.is_retryable() => {
+                health.error_rate += SMOOTHING * (1.0 - health.error_rate);
+                health.consecutive_failures += 1;
+                let cooldown = BASE_COOLDOWN
+                    .saturating_mul(1 << (health.consecutive_failures - 1).min(16))
+                    .min(MAX_COOLDOWN);
+                health.down_until = Some(Instant::now() + cooldown);
+                println!("RPC endpoint {} failed ({}), avoiding it for {:?}", self.name(), err, cooldown);
+            }
+            _ => {
+                let ms = latency.as_secs_f64() * 1_000.0;
+                health.latency_ms = Some(match health.latency_ms {
+                    Some(avg) => avg + SMOOTHING * (ms - avg),
+                    None => ms,
+                });
+                health.error_rate -= SMOOTHING * health.error_rate;
+                health.consecutive_failures = 0;
+                health.down_until = None;
+            }
+        }
+    }
+
+    pub fn record_head(&self, head: u64) {
+        self.health.lock().unwrap().head = Some(head);
+    }
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.latency_ms {
+            Some(ms) => write!(f, "{:.0} ms", ms)?,
+            None => write!(f, "untested")?,
+        }
+        write!(f, ", {:.0}% errors", self.error_rate * 100.0)?;
+        if let Some(head) = self.head {
+            write!(f, ", head {}", head)?;
+        }
+        if self.is_down(Instant::now()) {
+            write!(f, ", cooling down")?;
+        }
+        Ok(())
+    }
+}
+
+/// Host part of `url`: provider URLs often embed an API key in the path, so logs show
+/// only this.
+pub fn host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    rest.split(['/', '?']).next().unwrap_or(rest)
+}
+
+/// Index of the endpoint for the next request: the best-scoring one among those not
+/// cooling down after a failure, not lagging more than `MAX_LAG_BLOCKS` behind the best
+/// known head and, for a request about block `block`, not reporting a head below it. When
+/// none qualifies, one that has reached `block`, preferring the one whose cooldown ends
+/// first. Ties go to the endpoint listed first.
+pub fn pick(endpoints: &[Endpoint], now: Instant, block: Option<u64>) -> usize {
+    let health: Vec<Health> = endpoints.iter().map(Endpoint::health).collect();
+    let best_head = health.iter().filter_map(|h| h.head).max();
+    let lagging = |h: &Health| matches!((h.head, best_head), (Some(head), Some(best)) if head + MAX_LAG_BLOCKS < best);
+    let usable = health
+        .iter()
+        .enumerate()
+        .filter(|(_, h)| !h.is_down(now) && !lagging(h) && !h.is_behind(block))
+        .min_by(|(_, a), (_, b)| a.score().total_cmp(&b.score()));
+    match usable {
+        Some((i, _)) => i,
+        None => health
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, h)| (h.is_behind(block), lagging(h), h.down_until))
+            .map_or(0, |(i, _)| i),
+    }
+}
+
+/// Whether some endpoint other than `except` could take a request about `block` right now.
+pub fn has_alternative(endpoints: &[Endpoint], except: usize, now: Instant, block: Option<u64>) -> bool {
+    endpoints.iter().enumerate().any(|(i, e)| {
+        let health = e.health();
+        i != except && !health.is_down(now) && !health.is_behind(block)
+    })
+}
@@ -0,0 +1,160 @@
+use anyhow::{anyhow, Result}; // Error handling
+use ethnum::U256; // Exact 256-bit token amounts
+
+use crate::amount::{self, NetAmount};
+use crate::config::Token;
+use crate::netflow::Flow;
+use crate::sql::{Sql, SqlValue};
+
+const HOUR: i64 = 3_600;
+const DAY: i64 = 86_400;
+
+/// Adds (or with `add == false` removes) `flow` as an inflow or outflow of `exchange` to
+/// its per-block, per-hour and per-day `net_flow_history` buckets. Hour and day buckets
+/// are skipped for legacy rows without a unix timestamp.
+pub async fn record(conn: &mut impl Sql, exchange: &str, token: &Token, flow: &Flow, inflow: bool, add: bool) -> Result<()> {
+    for bucket in buckets(flow) {
+        bump(conn, exchange, token, bucket, flow.raw, inflow, add).await?;
+    }
+    Ok(())
+}
+
+/// The `(granularity, bucket_start)` buckets `flow` falls into.
+fn buckets(flow: &Flow) -> Vec<(&'static str, i64)> {
+    let mut buckets = vec![("block", flow.block_number as i64)];
+    if let Some(time) = flow.block_time {
+        buckets.push(("hour", time - time.rem_euclid(HOUR)));
+        buckets.push(("day", time - time.rem_euclid(DAY)));
+    }
+    buckets
+}
+
+async fn bump(
+    conn: &mut impl Sql,
+    exchange: &str,
+    token: &Token,
+    (granularity, bucket_start): (&str, i64),
+    raw: U256,
+    inflow: bool,
+    add: bool,
+) -> Result<()> {
+    let key: [SqlValue; 4] = [exchange.into(), token.address.to_lower_hex().into(), granularity.into(), bucket_start.into()];
+    let current = conn
+        .query_opt(
+            "SELECT inflow_raw, outflow_raw FROM net_flow_history
+             WHERE exchange = ?1 AND token_address = ?2 AND granularity = ?3 AND bucket_start = ?4",
+            &key,
+        )
+        .await?;
+    let (mut total_in, mut total_out) = match current {
+        Some(row) => (amount::parse_raw(row[0].as_str()?)?, amount::parse_raw(row[1].as_str()?)?),
+        None => (U256::ZERO, U256::ZERO),
+    };
+    let side = if inflow { &mut total_in } else { &mut total_out };
+    *side = if add { side.checked_add(raw) } else { side.checked_sub(raw) }
+        .ok_or_else(|| anyhow!("{} bucket {} of {} out of range", granularity, bucket_start, exchange))?;
+
+    if total_in == U256::ZERO && total_out == U256::ZERO {
+        // Everything in the bucket was reverted.
+        conn.execute(
+            "DELETE FROM net_flow_history
+             WHERE exchange = ?1 AND token_address = ?2 AND granularity = ?3 AND bucket_start = ?4",
+            &key,
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let mut net = NetAmount::default();
+    net.add(total_in)?;
+    net.sub(total_out)?;
+    let [exchange, token_address, granularity, bucket_start] = key;
+    conn.execute(
+        "INSERT INTO net_flow_history
+             (exchange, token_address, granularity, bucket_start, inflow_raw, outflow_raw, net_raw, inflow, outflow, net)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+         ON CONFLICT (exchange, token_address, granularity, bucket_start) DO UPDATE SET
+             inflow_raw = excluded.inflow_raw,
+             outflow_raw = excluded.outflow_raw,
+             net_raw = excluded.net_raw,
+             inflow = excluded.inflow,
+             outflow = excluded.outflow,
+             net = excluded.net",
+        &[
+            exchange,
+            token_address,
+            granularity,
+            bucket_start,
+            total_in.to_string().into(),
+            total_out.to_string().into(),
+            net.0.to_string().into(),
+            amount::to_units(total_in, token.decimals).into(),
+            amount::to_units(total_out, token.decimals).into(),
+            net.to_units(token.decimals).into(),
+        ],
+    )
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{memory_db, test_config, BINANCE, OTHER};
+    use rusqlite::Connection;
+
+    fn flow(block_number: u64, block_time: Option<i64>, raw: u32) -> Flow {
+        Flow {
+            from: OTHER.parse().unwrap(),
+            to: BINANCE.parse().unwrap(),
+            token_address: test_config().tokens[0].address,
+            raw: U256::from(raw),
+            block_number,
+            block_time,
+        }
+    }
+
+    fn rows(conn: &Connection) -> Vec<(String, i64, String, String, String)> {
+        let mut stmt = conn
+            .prepare(
+                "SELECT granularity, bucket_start, inflow_raw, outflow_raw, net_raw FROM net_flow_history
+                 ORDER BY granularity, bucket_start",
+            )
+            .unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn buckets_by_block_hour_and_day() {
+        // 2025-09-20T12:34:56Z
+        assert_eq!(
+            buckets(&flow(7, Some(1_758_371_696), 1)),
+            [("block", 7), ("hour", 1_758_369_600), ("day", 1_758_326_400)]
+        );
+        // Legacy rows without a timestamp only get a block bucket.
+        assert_eq!(buckets(&flow(7, None, 1)), [("block", 7)]);
+    }
+
+    #[tokio::test]
+    async fn records_and_removes_flows() {
+        let config = test_config();
+        let token = &config.tokens[0];
+        let mut conn = memory_db();
+        let noon = 1_758_369_600;
+
+        record(&mut conn, "Binance", token, &flow(1, Some(noon), 10), true, true).await.unwrap();
+        record(&mut conn, "Binance", token, &flow(2, Some(noon + 60), 3), false, true).await.unwrap();
+        let hour = |rows: Vec<(String, i64, String, String, String)>| rows.into_iter().find(|r| r.0 == "hour").unwrap();
+        assert_eq!(hour(rows(&conn)), ("hour".into(), noon, "10".into(), "3".into(), "7".into()));
+        assert_eq!(rows(&conn).iter().filter(|r| r.0 == "block").count(), 2);
+
+        // Removing a flow takes it back out, and a bucket left empty is deleted.
+        record(&mut conn, "Binance", token, &flow(2, Some(noon + 60), 3), false, false).await.unwrap();
+        assert_eq!(hour(rows(&conn)), ("hour".into(), noon, "10".into(), "0".into(), "10".into()));
+        record(&mut conn, "Binance", token, &flow(1, Some(noon), 10), true, false).await.unwrap();
+        assert!(rows(&conn).is_empty());
+    }
+}
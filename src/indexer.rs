@@ -0,0 +1,918 @@
+use anyhow::{anyhow, bail, Result}; // Error handling
+use rusqlite::Connection; // Connects to SQLite database
+use serde_json::Value; // Parses JSON
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::mpsc; // Receives WebSocket events
+
+use crate::address::Address;
+use crate::config::{Config, TraceMode};
+use crate::decode::{self, Transfer, NATIVE_TOKEN_ADDRESS};
+use crate::quorum::{self, Disagreement};
+use crate::rpc::{self, BlockHeader, RpcClient, RpcError};
+use crate::storage::Storage;
+use crate::trace;
+use crate::ws;
+
+/// Depth at which a block counts as confirmed unless configured otherwise.
+pub const DEFAULT_CONFIRMATIONS: u64 = 12;
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// With a WebSocket subscription, poll anyway after this long without an event.
+const WS_FALLBACK_POLL: Duration = Duration::from_secs(30);
+const BACKFILL_MAX_WINDOW: u64 = 2_000;
+/// Deepest reorg the follower will unwind before giving up.
+const MAX_REORG_DEPTH: u64 = 256;
+
+/// The indexer as a library: a store, an RPC client and the tracked exchanges and tokens.
+/// Build it with `new` (or `open` for SQLite) and the `with_*` options, then drive it with
+/// `catch_up`, `backfill` or `follow`.
+pub struct Indexer<S: Storage = Connection> {
+    store: S,
+    rpc: RpcClient,
+    config: Config,
+    confirmations: u64,
+    ws_url: Option<String>,
+}
+
+impl Indexer<Connection> {
+    /// Opens (or creates) the SQLite database at `path`.
+    pub async fn open(config: Config, path: impl AsRef<Path>, rpc: RpcClient) -> Result<Self> {
+        Self::new(config, Connection::open(path)?, rpc).await
+    }
+}
+
+impl<S: Storage> Indexer<S> {
+    /// Wraps a store, applying any pending schema migrations.
+    pub async fn new(config: Config, mut store: S, rpc: RpcClient) -> Result<Self> {
+        if let Some(quorum) = config.quorum
+            && quorum.agree > rpc.endpoint_names().len()
+        {
+            bail!("quorum.agree is {} but only {} RPC endpoints are configured", quorum.agree, rpc.endpoint_names().len());
+        }
+        store.migrate(false).await?;
+        Ok(Self { store, rpc, config, confirmations: DEFAULT_CONFIRMATIONS, ws_url: None })
+    }
+
+    /// Depth at which an indexed block is marked confirmed. Blocks are still indexed up to
+    /// the chain head as soon as they appear; this only decides when they stop counting as
+    /// pending. Consumers that want settled net-flow figures read the finalized ones.
+    pub fn with_confirmations(mut self, confirmations: u64) -> Self {
+        self.confirmations = confirmations;
+        self
+    }
+
+    /// Makes `follow` wake on `eth_subscribe` events from this WebSocket URL instead of
+    /// polling.
+    pub fn with_websocket(mut self, url: String) -> Self {
+        self.ws_url = Some(url);
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn rpc(&self) -> &RpcClient {
+        &self.rpc
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn store_mut(&mut self) -> &mut S {
+        &mut self.store
+    }
+
+    /// Indexes every new block up to the head; see the free function `catch_up`.
+    pub async fn catch_up(&mut self) -> Result<u64> {
+        catch_up(&mut self.store, &self.rpc, &self.config, self.confirmations).await
+    }
+
+    /// Indexes the transfers of an inclusive historical range; see the free function `backfill`.
+    pub async fn backfill(&mut self, from: u64, to: u64) -> Result<usize> {
+        if from > to {
+            bail!("backfill range is empty: {} > {}", from, to);
+        }
+        backfill(&mut self.store, &self.rpc, from, to, &self.config).await
+    }
+
+    /// Recomputes net-flow and its history from all transfers and returns the pairs whose
+    /// incremental value disagreed.
+    pub async fn rebuild_net_flow(&mut self) -> Result<Vec<String>> {
+        self.store.rebuild_net_flow(&self.config).await
+    }
+
+    /// Follows the chain head forever. RPC failures that outlast the client's retries are
+    /// logged and retried on the next poll; anything else (database, decoding) is returned.
+    pub async fn follow(&mut self) -> Result<()> {
+        if let Some(last) = self.store.last_indexed_block().await? {
+            println!("Resuming after block {}", last);
+        }
+        let mut events = self.ws_url.clone().map(|url| {
+            let (addresses, topics) = self.config.log_filter();
+            ws::Subscriber::new(url).with_logs(&addresses, &topics).spawn()
+        });
+        loop {
+            let processed = match self.catch_up().await {
+                Ok(processed) => processed,
+                Err(err) if RpcError::of(&err).is_some() => {
+                    println!("RPC error, retrying after the poll interval: {:#}", err);
+                    for (name, health) in self.rpc.health() {
+                        println!("  {}: {}", name, health);
+                    }
+                    0
+                }
+                Err(err) => return Err(err),
+            };
+            if processed > 0 {
+                println!(
+                    "Indexed {} blocks up to {} (finalized through {}) at {}",
+                    processed,
+                    self.store.last_indexed_block().await?.unwrap_or(0),
+                    self.store.finalized_block().await?.map_or("unknown".to_string(), |n| n.to_string()),
+                    chrono::Utc::now()
+                );
+            }
+            match &mut events {
+                Some(receiver) => {
+                    if !wait_for_event(receiver).await {
+                        events = None;
+                    }
+                }
+                None => tokio::time::sleep(POLL_INTERVAL).await,
+            }
+        }
+    }
+}
+
+/// Waits for the next WebSocket event, or `WS_FALLBACK_POLL` if none comes, and discards
+/// any queued behind it: one catch-up covers them all. Returns false once the subscription
+/// has ended.
+async fn wait_for_event(events: &mut mpsc::Receiver<ws::Event>) -> bool {
+    match tokio::time::timeout(WS_FALLBACK_POLL, events.recv()).await {
+        Ok(None) => {
+            println!("WebSocket subscription ended, falling back to polling");
+            return false;
+        }
+        Ok(Some(ws::Event::Connected)) => println!("WebSocket subscribed, filling any gap over HTTP"),
+        _ => {}
+    }
+    while events.try_recv().is_ok() {}
+    true
+}
+
+/// Result of trying to append one block on top of the indexed chain.
+#[derive(Debug, PartialEq)]
+pub enum BlockOutcome {
+    /// The block extended the chain; carries the number of tracked transfers inserted.
+    Indexed(usize),
+    /// The block's parent hash differs from the stored hash of the previous height.
+    ParentMismatch,
+    /// Too few endpoints agreed on the block; it was quarantined instead of indexed.
+    Quarantined(Disagreement),
+}
+
+/// Fetches and stores the tracked token transfers of one block, recording its hash and
+/// advancing the checkpoint in the same transaction so a crash never leaves a
+/// half-indexed block behind.
+pub async fn index_block<S: Storage>(store: &mut S, rpc: &RpcClient, number: u64, config: &Config) -> Result<BlockOutcome> {
+    let full_transactions = config.tracks_native() && config.native_trace().is_none();
+    let (header, transactions) = rpc.block(number, full_transactions).await?;
+    if number > 0
+        && let Some(prev) = store.block_hash(number - 1).await?
+        && prev != header.parent_hash
+    {
+        return Ok(BlockOutcome::ParentMismatch);
+    }
+    let (addresses, topics) = config.log_filter();
+    let logs = rpc.get_logs_by_hash(&header, &addresses, &topics).await?;
+    if let Some(quorum) = &config.quorum
+        && let Some(disagreement) = quorum::verify(rpc, quorum, &header, &logs, config).await?
+    {
+        store
+            .quarantine_block(number, &header.hash, disagreement.kind, &disagreement.answers_json())
+            .await?;
+        return Ok(BlockOutcome::Quarantined(disagreement));
+    }
+
+    let mut transfers = tracked_transfers(&logs, config)?;
+    if config.tracks_native() {
+        let native = native_transfers(rpc, &header, &transactions, config).await?;
+        transfers.extend(unlogged_native(native, &transfers, config));
+    }
+    attach_receipts(rpc, &[header.hash.as_str()], header.number, &mut transfers).await?;
+
+    store.commit_block(config, &header, &transfers).await?;
+    Ok(BlockOutcome::Indexed(transfers.len()))
+}
+
+/// Walks back from `from` until the stored block hash matches the canonical chain and
+/// returns that height. Heights without a stored hash are trusted as-is.
+pub async fn find_common_ancestor<S: Storage>(store: &mut S, rpc: &RpcClient, from: u64) -> Result<u64> {
+    let mut number = from;
+    for _ in 0..MAX_REORG_DEPTH {
+        let Some(stored) = store.block_hash(number).await? else {
+            return Ok(number);
+        };
+        if rpc.block_header(number).await?.hash == stored {
+            return Ok(number);
+        }
+        if number == 0 {
+            break;
+        }
+        number -= 1;
+    }
+    bail!("no common ancestor within {} blocks below {}", MAX_REORG_DEPTH, from)
+}
+
+/// Decodes `logs` and keeps the transfers of configured tokens touching a tracked wallet,
+/// each paired with its token's decimals.
+fn tracked_transfers(logs: &[Value], config: &Config) -> Result<Vec<(Transfer, u32)>> {
+    let mut transfers = Vec::new();
+    for log in logs {
+        if let Some(transfer) = decode::decode_log(log)? {
+            transfers.push(transfer);
+        }
+    }
+    Ok(keep_tracked(transfers, config))
+}
+
+fn keep_tracked(transfers: Vec<Transfer>, config: &Config) -> Vec<(Transfer, u32)> {
+    let tracked: HashSet<Address> = config.tracked_addresses().into_iter().collect();
+    transfers
+        .into_iter()
+        .filter(|t| tracked.contains(&t.from) || tracked.contains(&t.to))
+        .filter_map(|t| {
+            let decimals = config.token(t.token_address)?.decimals;
+            Some((t, decimals))
+        })
+        .collect()
+}
+
+/// Native transfers of a block taken from its transactions rather than its logs: every
+/// value-bearing call of the call tree in trace mode, otherwise just top-level values.
+async fn native_transfers(
+    rpc: &RpcClient,
+    header: &BlockHeader,
+    transactions: &[Value],
+    config: &Config,
+) -> Result<Vec<Transfer>> {
+    match config.native_trace() {
+        None => decode::decode_tx_values(header.number, transactions),
+        Some(TraceMode::CallTracer) => {
+            let tx_hashes: Vec<String> = transactions
+                .iter()
+                .filter_map(|tx| tx.as_str().or_else(|| tx["hash"].as_str()))
+                .map(str::to_lowercase)
+                .collect();
+            let traces = rpc.debug_trace_block(&header.hash).await?;
+            trace::decode_call_tracer(header.number, &traces, &tx_hashes)
+        }
+        Some(TraceMode::TraceBlock) => {
+            let traces = rpc.trace_block(header.number).await?;
+            // `trace_block` only takes a number; make sure it traced the block we checked.
+            if let Some(other) = traces
+                .iter()
+                .filter_map(|t| t["blockHash"].as_str())
+                .find(|h| !h.eq_ignore_ascii_case(&header.hash))
+            {
+                bail!("trace_block {} returned block {} instead of {}", header.number, other, header.hash);
+            }
+            trace::decode_trace_block(header.number, &traces)
+        }
+    }
+}
+
+/// Keeps the tracked native transfers among `candidates` that Bor did not already report
+/// through a `LogTransfer` in `from_logs`. Each log accounts for one candidate with the same
+/// transaction, sender, receiver and amount, so the two sources never double count while
+/// repeated identical payouts within one transaction are all kept.
+fn unlogged_native(candidates: Vec<Transfer>, from_logs: &[(Transfer, u32)], config: &Config) -> Vec<(Transfer, u32)> {
+    let mut logged: Vec<&Transfer> = from_logs
+        .iter()
+        .map(|(t, _)| t)
+        .filter(|t| t.token_address == NATIVE_TOKEN_ADDRESS)
+        .collect();
+    keep_tracked(candidates, config)
+        .into_iter()
+        .filter(|(candidate, _)| {
+            let seen = logged.iter().position(|l| {
+                l.tx_hash == candidate.tx_hash
+                    && l.from == candidate.from
+                    && l.to == candidate.to
+                    && l.amount_raw == candidate.amount_raw
+            });
+            match seen {
+                Some(i) => {
+                    logged.swap_remove(i);
+                    false
+                }
+                None => true,
+            }
+        })
+        .collect()
+}
+
+/// Fetches the receipts of the transactions in `blocks` (hashes or hex numbers, the newest
+/// at height `newest`) and attaches them to `transfers`, which all come from those blocks.
+/// No transfers, no call.
+async fn attach_receipts(rpc: &RpcClient, blocks: &[&str], newest: u64, transfers: &mut [(Transfer, u32)]) -> Result<()> {
+    if transfers.is_empty() {
+        return Ok(());
+    }
+    let mut tx_hashes: Vec<&str> = transfers.iter().map(|(t, _)| t.tx_hash.as_str()).collect();
+    tx_hashes.sort_unstable();
+    tx_hashes.dedup();
+    let receipts = decode::decode_receipts(&rpc.block_receipts(blocks, &tx_hashes, newest).await?)?;
+    for (transfer, _) in transfers.iter_mut() {
+        transfer.receipt = receipts.get(&transfer.tx_hash).copied();
+    }
+    Ok(())
+}
+
+/// Indexes every block after the checkpoint up to the chain head (not `confirmations`
+/// behind it: blocks at the tip are stored as pending and unwound on a reorg), rolling back
+/// orphaned blocks when the chain reorganizes underneath us, then marks blocks
+/// `confirmations` deep as confirmed and those the node reports final as finalized,
+/// refreshing the finalized net-flow figures. A fresh database starts at the current head instead of genesis.
+pub async fn catch_up<S: Storage>(store: &mut S, rpc: &RpcClient, config: &Config, confirmations: u64) -> Result<u64> {
+    let head = rpc.block_number().await?;
+    let mut block = match store.last_indexed_block().await? {
+        Some(last) => last + 1,
+        None => head,
+    };
+
+    let mut processed = 0;
+    while block <= head {
+        match index_block(store, rpc, block, config).await? {
+            BlockOutcome::Indexed(inserted) => {
+                if inserted > 0 {
+                    println!("Block {}: inserted {} tracked transfers", block, inserted);
+                }
+                processed += 1;
+                block += 1;
+            }
+            BlockOutcome::ParentMismatch => {
+                let ancestor = find_common_ancestor(store, rpc, block - 1).await?;
+                if let Some(finalized) = store.finalized_block().await?
+                    && ancestor < finalized
+                {
+                    bail!("reorg at block {} reaches below finalized block {}", block, finalized);
+                }
+                let deleted = store.rollback_to(config, ancestor).await?;
+                println!(
+                    "Reorg detected at block {}: rolled back to {}, removed {} orphaned transfers",
+                    block, ancestor, deleted
+                );
+                block = ancestor + 1;
+            }
+            BlockOutcome::Quarantined(disagreement) => {
+                // Later blocks build on this one, so stop here and ask again next poll.
+                println!("Block {} quarantined, only {}", block, disagreement);
+                break;
+            }
+        }
+    }
+
+    // A node that lags or lacks the tag must not move finality backwards.
+    let finalized = rpc.finalized_block().await?.max(store.finalized_block().await?);
+    store.update_finality(config, head.saturating_sub(confirmations), finalized).await?;
+    Ok(processed)
+}
+
+/// Walks `from..=to` in `eth_getLogs` windows, halving the window whenever the provider
+/// rejects a query for returning too many results and growing it back after successes.
+/// Rows are keyed by `(tx_hash, log_index)`, so re-running an overlapping range is harmless.
+/// The follower checkpoint and `blocks` table are left untouched.
+///
+/// Only logs are read: native POL is seen through MRC20 `LogTransfer` events, but
+/// transaction values without one and internal transfers that only traces reveal are not
+/// backfilled, as that would mean fetching every block of the range.
+pub async fn backfill<S: Storage>(store: &mut S, rpc: &RpcClient, from: u64, to: u64, config: &Config) -> Result<usize> {
+    if from > to {
+        return Ok(0);
+    }
+    let mut window = BACKFILL_MAX_WINDOW;
+    let mut start = from;
+    let mut inserted = 0;
+    loop {
+        let end = to.min(start.saturating_add(window - 1));
+        let (addresses, topics) = config.log_filter();
+        let logs = match rpc.get_logs(start, end, &addresses, &topics).await {
+            Ok(logs) => logs,
+            Err(err) if window > 1 && rpc::is_too_many_results(&err) => {
+                window /= 2;
+                println!("Too many results for {}..={}, shrinking window to {} blocks", start, end, window);
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+
+        // Headers (for timestamps) and receipts of the blocks holding tracked transfers
+        // are fetched in batches rather than one request per block.
+        let mut transfers = tracked_transfers(&logs, config)?;
+        if let Some((t, _)) = transfers.iter().find(|(t, _)| !(start..=end).contains(&t.block_number)) {
+            bail!("eth_getLogs {}..={} returned a log of block {} (tx {})", start, end, t.block_number, t.tx_hash);
+        }
+        let mut numbers: Vec<u64> = transfers.iter().map(|(t, _)| t.block_number).collect();
+        numbers.sort_unstable();
+        numbers.dedup();
+        let ids: Vec<String> = numbers.iter().map(|n| format!("0x{:x}", n)).collect();
+        let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+        attach_receipts(rpc, &ids, end, &mut transfers).await?;
+        let times: HashMap<u64, i64> = rpc
+            .block_headers(&numbers)
+            .await?
+            .into_iter()
+            .map(|h| (h.number, h.timestamp))
+            .collect();
+
+        let rows: Vec<(Transfer, u32, i64)> = transfers
+            .into_iter()
+            .map(|(t, d)| {
+                let time = *times
+                    .get(&t.block_number)
+                    .ok_or_else(|| anyhow!("no header fetched for block {}", t.block_number))?;
+                Ok((t, d, time))
+            })
+            .collect::<Result<_>>()?;
+        store.store_transfers(config, &rows).await?;
+        let count = rows.len();
+        inserted += count;
+        println!("Backfilled {}..={}: {} tracked transfers", start, end, count);
+
+        if end == to {
+            return Ok(inserted);
+        }
+        start = end + 1;
+        window = (window * 2).min(BACKFILL_MAX_WINDOW);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use crate::config::Quorum;
+    use crate::testutil::{
+        memory_db, test_config, FakeChain, FakeRpc, FakeWs, BINANCE, FAKE_GAS_PRICE, FAKE_GAS_USED, GENESIS_TIME,
+        OTHER, POL_TOKEN_ADDRESS,
+    };
+
+    const ONE_POL: u128 = 1_000_000_000_000_000_000;
+
+    fn tx_hashes(conn: &Connection) -> Vec<String> {
+        let mut stmt = conn.prepare("SELECT tx_hash FROM transfers ORDER BY block_number, log_index").unwrap();
+        stmt.query_map([], |row| row.get(0)).unwrap().map(|r| r.unwrap()).collect()
+    }
+
+    fn binance_net_flow(conn: &Connection) -> f64 {
+        conn.query_row(
+            "SELECT cumulative_amount FROM net_flow WHERE exchange = 'Binance' AND token_address = ?1",
+            [POL_TOKEN_ADDRESS],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn resumes_from_checkpoint() {
+        let mut chain = FakeChain::linear(100, 103, 0);
+        chain.add_transfer(101, OTHER, BINANCE, ONE_POL, 1);
+        let fake = FakeRpc::start(chain.clone()).await;
+        let rpc = RpcClient::new(vec![fake.url.clone()]).unwrap();
+        let config = test_config();
+        let mut conn = memory_db();
+        db::set_last_indexed_block(&conn, 99).unwrap();
+
+        assert_eq!(catch_up(&mut conn, &rpc, &config, 0).await.unwrap(), 4);
+        assert_eq!(catch_up(&mut conn, &rpc, &config, 0).await.unwrap(), 0);
+
+        let mut longer = FakeChain::linear(100, 105, 0);
+        longer.add_transfer(101, OTHER, BINANCE, ONE_POL, 1);
+        longer.add_transfer(105, BINANCE, OTHER, ONE_POL, 2);
+        fake.set_chain(longer);
+        assert_eq!(catch_up(&mut conn, &rpc, &config, 0).await.unwrap(), 2);
+        assert_eq!(db::last_indexed_block(&conn).unwrap(), Some(105));
+        assert_eq!(tx_hashes(&conn).len(), 2);
+
+        let (iso, unix): (String, i64) = conn
+            .query_row("SELECT timestamp, timestamp_unix FROM transfers WHERE block_number = 105", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(unix, (GENESIS_TIME + 210) as i64);
+        assert_eq!(iso, "2023-11-14T22:16:50Z");
+    }
+
+    #[tokio::test]
+    async fn fresh_database_starts_at_the_head() {
+        let mut chain = FakeChain::linear(100, 105, 0);
+        chain.add_transfer(101, OTHER, BINANCE, ONE_POL, 1);
+        chain.add_transfer(105, OTHER, BINANCE, 2 * ONE_POL, 2);
+        let fake = FakeRpc::start(chain.clone()).await;
+        let rpc = RpcClient::new(vec![fake.url.clone()]).unwrap();
+        let config = test_config();
+        let mut conn = memory_db();
+        assert_eq!(db::last_indexed_block(&conn).unwrap(), None);
+
+        // Only the head is indexed; history is left to backfill.
+        assert_eq!(catch_up(&mut conn, &rpc, &config, 0).await.unwrap(), 1);
+        assert_eq!(db::last_indexed_block(&conn).unwrap(), Some(105));
+        assert_eq!(tx_hashes(&conn), vec![format!("0x{:064x}", 2)]);
+
+        // An Indexer over the same store resumes from the checkpoint, with nothing new to do.
+        let mut indexer = Indexer::new(config, conn, rpc).await.unwrap().with_confirmations(0);
+        assert_eq!(indexer.catch_up().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn catches_up_past_a_lagging_backup_endpoint() {
+        let mut chain = FakeChain::linear(100, 105, 0);
+        chain.add_transfer(104, OTHER, BINANCE, ONE_POL, 1);
+        let primary = FakeRpc::start(chain.clone()).await;
+        let backup = FakeRpc::start(FakeChain::linear(100, 103, 0)).await;
+        let rpc = RpcClient::new(vec![primary.url.clone(), backup.url.clone()]).unwrap();
+        let config = test_config();
+        let mut conn = memory_db();
+        db::set_last_indexed_block(&conn, 103).unwrap();
+
+        // One 503 leaves the primary scoring worse than the backup, which is at 103.
+        primary.fail_next(503, 1);
+        rpc.block_header(100).await.unwrap();
+
+        assert_eq!(catch_up(&mut conn, &rpc, &config, 0).await.unwrap(), 2);
+        assert_eq!(db::last_indexed_block(&conn).unwrap(), Some(105));
+        assert_eq!(tx_hashes(&conn), vec![format!("0x{:064x}", 1)]);
+    }
+
+    #[tokio::test]
+    async fn backfilled_transfers_carry_their_own_block_times() {
+        let mut chain = FakeChain::linear(100, 104, 0);
+        chain.add_transfer(101, OTHER, BINANCE, ONE_POL, 1);
+        chain.add_transfer(103, BINANCE, OTHER, ONE_POL, 2);
+        chain.add_transfer(103, OTHER, BINANCE, ONE_POL, 3);
+        let fake = FakeRpc::start(chain).await;
+        let rpc = RpcClient::new(vec![fake.url.clone()]).unwrap();
+        let mut conn = memory_db();
+
+        assert_eq!(backfill(&mut conn, &rpc, 100, 104, &test_config()).await.unwrap(), 3);
+
+        let mut stmt = conn
+            .prepare("SELECT block_number, timestamp, timestamp_unix FROM transfers ORDER BY block_number, log_index")
+            .unwrap();
+        let rows: Vec<(u64, String, i64)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        let expected: Vec<(u64, String, i64)> = [101, 103, 103]
+            .into_iter()
+            .map(|n| {
+                let unix = (GENESIS_TIME + 2 * n) as i64;
+                (n, db::iso_timestamp(unix).unwrap(), unix)
+            })
+            .collect();
+        assert_eq!(rows, expected);
+    }
+
+    /// Waits up to ten seconds for another connection to see the checkpoint reach `block`.
+    async fn indexed_through(conn: &Connection, block: u64) {
+        for _ in 0..1_000 {
+            if db::last_indexed_block(conn).unwrap() == Some(block) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("block {} not indexed, checkpoint at {:?}", block, db::last_indexed_block(conn).unwrap());
+    }
+
+    #[tokio::test]
+    async fn follow_wakes_on_websocket_events_and_fills_gaps_after_reconnecting() {
+        let fake = FakeRpc::start(FakeChain::linear(100, 101, 0)).await;
+        let ws = FakeWs::start().await;
+        let path = std::env::temp_dir().join(format!("indexer-follow-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let rpc = RpcClient::new(vec![fake.url.clone()]).unwrap();
+        let mut indexer = Indexer::open(test_config(), &path, rpc)
+            .await
+            .unwrap()
+            .with_confirmations(0)
+            .with_websocket(ws.url.clone());
+        db::set_last_indexed_block(indexer.store(), 99).unwrap();
+        let reader = Connection::open(&path).unwrap();
+
+        // Without events the follower would only look again after WS_FALLBACK_POLL (30 s),
+        // so every step below finishing within seconds shows an event woke it.
+        let script = async {
+            indexed_through(&reader, 101).await;
+            while ws.subscriptions().len() < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            fake.set_chain(FakeChain::linear(100, 103, 0));
+            ws.push_head(103);
+            indexed_through(&reader, 103).await;
+            // Let the follower consume the first `Connected` too, if it raced the head.
+            tokio::time::sleep(Duration::from_millis(200)).await;
+
+            // Blocks 104 and 105 arrive while the socket is down and are never announced;
+            // resubscribing makes the follower fetch them over HTTP.
+            fake.set_chain(FakeChain::linear(100, 105, 0));
+            ws.drop_connections();
+            indexed_through(&reader, 105).await;
+            assert_eq!(ws.connections(), 2);
+        };
+        tokio::select! {
+            result = indexer.follow() => panic!("follow returned {:?}", result),
+            _ = script => {}
+        }
+        drop((indexer, reader));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn backfill_shrinks_the_window_on_too_many_results_and_grows_it_back() {
+        let mut chain = FakeChain::linear(100, 6_000, 0);
+        for (i, n) in [150, 151, 152].into_iter().enumerate() {
+            chain.add_transfer(n, OTHER, BINANCE, ONE_POL, i as u64 + 1);
+        }
+        // Untracked, but still counts towards the provider's result limit.
+        chain.add_transfer(160, OTHER, OTHER, ONE_POL, 4);
+        chain.add_transfer(4_000, BINANCE, OTHER, 2 * ONE_POL, 5);
+        chain.add_native_transfer(4_100, OTHER, BINANCE, 3 * ONE_POL, 6, true);
+        // Without a LogTransfer only a block fetch would reveal it, which backfill skips.
+        chain.add_native_transfer(4_200, OTHER, BINANCE, 4 * ONE_POL, 7, false);
+        chain.max_logs = Some(2);
+        let fake = FakeRpc::start(chain).await;
+        let rpc = RpcClient::new(vec![fake.url.clone()]).unwrap();
+        let config = test_config();
+        let mut conn = memory_db();
+
+        assert_eq!(backfill(&mut conn, &rpc, 100, 6_000, &config).await.unwrap(), 5);
+        assert_eq!(tx_hashes(&conn), [1, 2, 3, 5, 6].map(|tag| format!("0x{:064x}", tag)));
+        let unix: i64 = conn
+            .query_row("SELECT timestamp_unix FROM transfers WHERE block_number = 4000", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(unix, (GENESIS_TIME + 8_000) as i64);
+        assert_eq!(db::last_indexed_block(&conn).unwrap(), None);
+
+        let ranges = fake.log_ranges();
+        assert_eq!(ranges[0], (100, 2_099));
+        let sizes: Vec<u64> = ranges.iter().map(|(start, end)| end - start + 1).collect();
+        assert!(sizes.iter().any(|&size| size <= 3), "{:?}", ranges);
+        // Past the crowded blocks the window is back at its maximum.
+        assert!(ranges.iter().any(|(start, end)| *start > 1_000 && end - start + 1 == BACKFILL_MAX_WINDOW), "{:?}", ranges);
+        assert_eq!(ranges.last().unwrap().1, 6_000);
+
+        // The last window ends at u64::MAX without overflowing.
+        assert_eq!(backfill(&mut conn, &rpc, u64::MAX - 5, u64::MAX, &config).await.unwrap(), 0);
+        assert_eq!(backfill(&mut conn, &rpc, 10, 9, &config).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn records_native_transfers_once() {
+        let mut chain = FakeChain::linear(100, 101, 0);
+        // Reported both as tx value and LogTransfer: must be stored once.
+        chain.add_native_transfer(100, OTHER, BINANCE, 3 * ONE_POL, 1, true);
+        // Tx value only, e.g. from a node that does not surface LogTransfer.
+        chain.add_native_transfer(101, BINANCE, OTHER, ONE_POL, 2, false);
+        let fake = FakeRpc::start(chain).await;
+        let rpc = RpcClient::new(vec![fake.url.clone()]).unwrap();
+        let config = test_config();
+        let mut conn = memory_db();
+        db::set_last_indexed_block(&conn, 99).unwrap();
+
+        catch_up(&mut conn, &rpc, &config, 0).await.unwrap();
+
+        let mut stmt = conn.prepare("SELECT log_index, token_address, amount_raw FROM transfers ORDER BY block_number").unwrap();
+        let rows: Vec<(i64, String, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (0, NATIVE_TOKEN_ADDRESS.to_lower_hex(), (3 * ONE_POL).to_string()),
+                (decode::TX_VALUE_LOG_INDEX, NATIVE_TOKEN_ADDRESS.to_lower_hex(), ONE_POL.to_string()),
+            ]
+        );
+        let native: String = conn
+            .query_row(
+                "SELECT cumulative_amount_raw FROM net_flow WHERE exchange = 'Binance' AND token_address = ?1",
+                [NATIVE_TOKEN_ADDRESS],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(native, (2 * ONE_POL).to_string());
+    }
+
+    #[tokio::test]
+    async fn stores_receipts_and_skips_failed_transactions_in_net_flow() {
+        let mut chain = FakeChain::linear(100, 100, 0);
+        chain.add_native_transfer(100, OTHER, BINANCE, 3 * ONE_POL, 1, false);
+        chain.add_native_transfer(100, OTHER, BINANCE, 5 * ONE_POL, 2, false);
+        chain.fail_tx(100, 2);
+        let fake = FakeRpc::start(chain).await;
+        let rpc = RpcClient::new(vec![fake.url.clone()]).unwrap();
+        let config = test_config();
+        let mut conn = memory_db();
+        db::set_last_indexed_block(&conn, 99).unwrap();
+
+        catch_up(&mut conn, &rpc, &config, 0).await.unwrap();
+
+        let mut stmt = conn.prepare("SELECT tx_fee_raw, receipt_status FROM transfers ORDER BY tx_hash").unwrap();
+        let rows: Vec<(String, i64)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        let fee = (FAKE_GAS_USED * FAKE_GAS_PRICE).to_string();
+        assert_eq!(rows, vec![(fee.clone(), 1), (fee, 0)]);
+        drop(stmt);
+        let native = |conn: &Connection| -> String {
+            conn.query_row(
+                "SELECT cumulative_amount_raw FROM net_flow WHERE exchange = 'Binance' AND token_address = ?1",
+                [NATIVE_TOKEN_ADDRESS],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(native(&conn), (3 * ONE_POL).to_string());
+        assert!(conn.rebuild_net_flow(&config).await.unwrap().is_empty());
+        assert_eq!(native(&conn), (3 * ONE_POL).to_string());
+    }
+
+    #[tokio::test]
+    async fn indexer_type_drives_the_pipeline() {
+        let mut chain = FakeChain::linear(100, 102, 0);
+        chain.add_transfer(101, OTHER, BINANCE, ONE_POL, 1);
+        let fake = FakeRpc::start(chain).await;
+        let rpc = || RpcClient::new(vec![fake.url.clone()]).unwrap();
+        let mut config = test_config();
+        config.quorum = Some(Quorum { agree: 2, check_logs: false });
+        assert!(Indexer::new(config.clone(), Connection::open_in_memory().unwrap(), rpc()).await.is_err());
+
+        config.quorum = None;
+        let mut indexer = Indexer::new(config, Connection::open_in_memory().unwrap(), rpc())
+            .await
+            .unwrap()
+            .with_confirmations(0);
+        db::set_last_indexed_block(indexer.store(), 99).unwrap();
+        assert_eq!(indexer.catch_up().await.unwrap(), 3);
+        assert_eq!(binance_net_flow(indexer.store()), 1.0);
+        assert!(indexer.rebuild_net_flow().await.unwrap().is_empty());
+        assert!(indexer.backfill(102, 101).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn tracks_block_status_and_finalized_net_flow() {
+        let mut chain = FakeChain::linear(100, 105, 0);
+        chain.add_transfer(101, OTHER, BINANCE, ONE_POL, 1);
+        chain.add_transfer(104, OTHER, BINANCE, 5 * ONE_POL, 2);
+        chain.finalized = Some(102);
+        let fake = FakeRpc::start(chain.clone()).await;
+        let rpc = RpcClient::new(vec![fake.url.clone()]).unwrap();
+        let config = test_config();
+        let mut conn = memory_db();
+        db::set_last_indexed_block(&conn, 99).unwrap();
+
+        catch_up(&mut conn, &rpc, &config, 2).await.unwrap();
+        // Indexed through the head, not `confirmations` behind it.
+        assert_eq!(db::last_indexed_block(&conn).unwrap(), Some(105));
+
+        let statuses = |conn: &Connection| -> Vec<String> {
+            let mut stmt = conn.prepare("SELECT status FROM blocks ORDER BY block_number").unwrap();
+            stmt.query_map([], |row| row.get(0)).unwrap().map(|r| r.unwrap()).collect()
+        };
+        assert_eq!(
+            statuses(&conn),
+            ["finalized", "finalized", "finalized", "confirmed", "pending", "pending"]
+        );
+        let figures = |conn: &Connection| -> (String, String) {
+            conn.query_row(
+                "SELECT cumulative_amount_raw, finalized_amount_raw FROM net_flow
+                 WHERE exchange = 'Binance' AND token_address = ?1",
+                [POL_TOKEN_ADDRESS],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap()
+        };
+        assert_eq!(figures(&conn), ((6 * ONE_POL).to_string(), ONE_POL.to_string()));
+        // Survives a rebuild from scratch.
+        assert!(conn.rebuild_net_flow(&config).await.unwrap().is_empty());
+        assert_eq!(figures(&conn), ((6 * ONE_POL).to_string(), ONE_POL.to_string()));
+
+        // An endpoint reporting an older finalized block does not move finality back.
+        chain.finalized = Some(101);
+        fake.set_chain(chain.clone());
+        catch_up(&mut conn, &rpc, &config, 2).await.unwrap();
+        assert_eq!(db::finalized_block(&conn).unwrap(), Some(102));
+
+        chain.finalized = Some(105);
+        fake.set_chain(chain.clone());
+        catch_up(&mut conn, &rpc, &config, 2).await.unwrap();
+        assert_eq!(statuses(&conn), ["finalized"; 6]);
+        assert_eq!(figures(&conn), ((6 * ONE_POL).to_string(), (6 * ONE_POL).to_string()));
+
+        // A reorg reaching into finalized blocks is refused rather than unwound.
+        fake.set_chain(chain.fork(103, 106, 1));
+        let err = catch_up(&mut conn, &rpc, &config, 2).await.unwrap_err();
+        assert!(err.to_string().contains("below finalized block 105"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn quarantines_blocks_without_quorum() {
+        let mut chain = FakeChain::linear(100, 107, 0);
+        chain.add_transfer(101, OTHER, BINANCE, ONE_POL, 1);
+        let primary = FakeRpc::start(chain.clone()).await;
+        let secondary = FakeRpc::start(chain.clone()).await;
+        // The dissenter lags far behind, so it is asked to vote but never serves the block.
+        let dissenter = FakeRpc::start(chain.fork(100, 101, 1)).await;
+        let rpc = RpcClient::new(vec![primary.url.clone(), secondary.url.clone(), dissenter.url.clone()]).unwrap();
+        let mut config = test_config();
+        config.quorum = Some(Quorum { agree: 3, check_logs: true });
+        let mut conn = memory_db();
+        db::set_last_indexed_block(&conn, 99).unwrap();
+
+        assert_eq!(catch_up(&mut conn, &rpc, &config, 0).await.unwrap(), 1);
+        assert_eq!(db::last_indexed_block(&conn).unwrap(), Some(100));
+        let quarantined = |conn: &Connection| -> Vec<(i64, String)> {
+            let mut stmt = conn.prepare("SELECT block_number, kind FROM quarantined_blocks ORDER BY kind").unwrap();
+            stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap().map(|r| r.unwrap()).collect()
+        };
+        assert_eq!(quarantined(&conn), vec![(101, "hash".to_string())]);
+
+        // Same hashes, but the transfer log is missing from the dissenter's answer.
+        dissenter.set_chain(FakeChain::linear(100, 101, 0));
+        assert_eq!(catch_up(&mut conn, &rpc, &config, 0).await.unwrap(), 0);
+        assert_eq!(quarantined(&conn), vec![(101, "hash".to_string()), (101, "logs".to_string())]);
+        assert!(tx_hashes(&conn).is_empty());
+
+        config.quorum = Some(Quorum { agree: 2, check_logs: true });
+        assert_eq!(catch_up(&mut conn, &rpc, &config, 0).await.unwrap(), 7);
+        assert_eq!(tx_hashes(&conn).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn rolls_back_orphaned_blocks_on_reorg() {
+        let mut chain_a = FakeChain::linear(100, 105, 0);
+        chain_a.add_transfer(102, OTHER, BINANCE, 2 * ONE_POL, 1);
+        chain_a.add_transfer(104, OTHER, BINANCE, 5 * ONE_POL, 2);
+        chain_a.add_transfer(105, BINANCE, OTHER, ONE_POL, 3);
+        let fake = FakeRpc::start(chain_a.clone()).await;
+        let rpc = RpcClient::new(vec![fake.url.clone()]).unwrap();
+        let config = test_config();
+        let mut conn = memory_db();
+        db::set_last_indexed_block(&conn, 99).unwrap();
+
+        catch_up(&mut conn, &rpc, &config, 0).await.unwrap();
+        assert_eq!(tx_hashes(&conn).len(), 3);
+
+        // Chain B forks after block 103 and is one block longer.
+        let mut chain_b = chain_a.fork(103, 106, 1);
+        chain_b.add_transfer(105, OTHER, BINANCE, 7 * ONE_POL, 4);
+        fake.set_chain(chain_b.clone());
+
+        assert_eq!(find_common_ancestor(&mut conn, &rpc, 105).await.unwrap(), 103);
+        assert_eq!(catch_up(&mut conn, &rpc, &config, 0).await.unwrap(), 3);
+
+        assert_eq!(tx_hashes(&conn), vec![format!("0x{:064x}", 1), format!("0x{:064x}", 4)]);
+        for n in 100..=106 {
+            assert_eq!(db::block_hash(&conn, n).unwrap(), Some(chain_b.blocks[&n].hash.clone()));
+        }
+        assert_eq!(db::last_indexed_block(&conn).unwrap(), Some(106));
+        // Orphaned +5 and -1 were reverted, the new branch's +7 applied.
+        assert_eq!(binance_net_flow(&conn), 9.0);
+        let raw: String = conn
+            .query_row(
+                "SELECT cumulative_amount_raw FROM net_flow WHERE exchange = 'Binance' AND token_address = ?1",
+                [POL_TOKEN_ADDRESS],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(raw, (9 * ONE_POL).to_string());
+        let buckets = |conn: &Connection, granularity: &str| -> Vec<(i64, String)> {
+            let mut stmt = conn
+                .prepare(
+                    "SELECT bucket_start, net_raw FROM net_flow_history
+                     WHERE granularity = ?1 AND token_address = ?2 ORDER BY bucket_start",
+                )
+                .unwrap();
+            stmt.query_map([granularity, POL_TOKEN_ADDRESS], |row| Ok((row.get(0)?, row.get(1)?)))
+                .unwrap()
+                .map(|r| r.unwrap())
+                .collect()
+        };
+        assert_eq!(buckets(&conn, "block"), vec![(102, (2 * ONE_POL).to_string()), (105, (7 * ONE_POL).to_string())]);
+        let hour = GENESIS_TIME as i64 - GENESIS_TIME as i64 % 3_600;
+        assert_eq!(buckets(&conn, "hour"), vec![(hour, (9 * ONE_POL).to_string())]);
+
+        // A rebuild from scratch agrees with the incrementally maintained tables.
+        let incremental = buckets(&conn, "block");
+        assert!(conn.rebuild_net_flow(&config).await.unwrap().is_empty());
+        assert_eq!(buckets(&conn, "block"), incremental);
+    }
+}
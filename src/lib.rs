@@ -0,0 +1,31 @@
+//! Indexes token transfers to and from exchange wallets on Polygon PoS into SQLite or
+//! PostgreSQL and keeps per-exchange net-flow figures up to date. `Indexer` ties the pieces together; the modules
+//! below can also be used on their own.
+
+pub mod address;
+pub mod amount;
+pub mod config;
+pub mod db;
+pub mod decode;
+pub mod fixtures;
+pub mod health;
+pub mod history;
+pub mod indexer;
+pub mod migrations;
+pub mod netflow;
+pub mod postgres;
+pub mod quorum;
+pub mod ratelimit;
+pub mod rpc;
+pub mod sql;
+pub mod storage;
+#[cfg(test)]
+mod testutil;
+pub mod trace;
+pub mod ws;
+
+pub use address::Address;
+pub use config::Config;
+pub use indexer::Indexer;
+pub use rpc::RpcClient;
+pub use storage::Storage;
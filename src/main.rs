@@ -1,228 +1,342 @@
-use anyhow::Result; // Error handling
+use anyhow::{bail, Context, Result}; // Error handling
+use clap::{Parser, Subcommand}; // Parses the command line
 use rusqlite::Connection; // Connects to SQLite database
-use reqwest::Client; // Makes HTTP requests to Alchemy
-use serde_json::Value; // Parses JSON 
-use std::env; // Gets the Alchemy URL from environment
-use std::time::Duration; // Sets a timeout
-use chrono; // For timestamps in the loop
+use std::env; // Reads the tuning variables
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use polygon_pol_indexer::amount::format_units;
+use polygon_pol_indexer::db::iso_timestamp;
+use polygon_pol_indexer::indexer::{self, Indexer};
+use polygon_pol_indexer::postgres::PgStorage;
+use polygon_pol_indexer::rpc::{self, RetryPolicy, RpcClient};
+use polygon_pol_indexer::{fixtures, Config, Storage};
+
+/// Indexes token transfers to and from exchange wallets on Polygon PoS and keeps
+/// per-exchange net-flow figures.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// SQLite database file, or a postgres:// URL to use PostgreSQL
+    #[arg(long, global = true, env = "DATABASE_URL", default_value = "data/polygon.db")]
+    db: String,
+
+    /// Exchanges and tokens to track (TOML, or JSON when the name ends in .json)
+    #[arg(long, global = true, env = "INDEXER_CONFIG", default_value = "config.toml")]
+    config: PathBuf,
+
+    /// RPC endpoint URL; repeat or separate with commas to fail over between several
+    #[arg(long, global = true, env = "POLYGON_RPC", value_delimiter = ',')]
+    rpc: Vec<String>,
+
+    /// What to do; follows the chain head (`index`) when omitted
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Follow the chain head, indexing blocks as they arrive and tracking their finality
+    Index,
+    /// Index a historical block range and exit
+    Backfill {
+        /// First block of the range
+        #[arg(long)]
+        from: u64,
+        /// Last block of the range (inclusive)
+        #[arg(long)]
+        to: u64,
+    },
+    /// Print the current net-flow of every exchange and token
+    Netflow,
+    /// Recompute net-flow from all stored transfers, reporting and correcting differences
+    RebuildNetflow,
+    /// Write stored transfers to a CSV file, addresses in EIP-55 checksum form
+    Export {
+        /// First block to export
+        #[arg(long, default_value_t = 0)]
+        from: u64,
+        /// Last block to export (inclusive); defaults to the newest
+        #[arg(long)]
+        to: Option<u64>,
+        /// File to write
+        #[arg(long, short, default_value = "transfers.csv")]
+        output: PathBuf,
+    },
+    /// Check the stored net-flow against the stored transfers without changing anything;
+    /// fails if they disagree
+    Verify,
+    /// Apply pending schema migrations and exit
+    Migrate {
+        /// Only list the pending migrations
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Store made-up transfers from a fixture file, for trying the indexer out. Never use
+    /// this on a database holding real data
+    LoadFixtures {
+        /// JSON fixture file
+        #[arg(default_value = fixtures::SAMPLE_TRANSFERS)]
+        file: PathBuf,
+    },
+}
 
 #[tokio::main] // Makes main async to handle network waits
 async fn main() -> Result<()> {
-    // 1. Open the database
-    let conn = Connection::open("data/polygon.db")?;
-    println!("Opened DB at data/polygon.db");
-
-    // 2. Create tables
-    conn.execute_batch(
-        "
-        CREATE TABLE IF NOT EXISTS transfers (
-            tx_hash TEXT NOT NULL,
-            log_index INTEGER NOT NULL,
-            block_number INTEGER NOT NULL,
-            timestamp TEXT NOT NULL,
-            from_addr TEXT NOT NULL,
-            to_addr TEXT NOT NULL,
-            token_address TEXT NOT NULL,
-            amount_raw TEXT NOT NULL,
-            amount REAL NOT NULL,
-            tx_fee_raw TEXT,
-            receipt_status INTEGER,
-            PRIMARY KEY (tx_hash, log_index)
-        );
-        CREATE INDEX IF NOT EXISTS idx_transfers_to ON transfers(to_addr);
-        CREATE INDEX IF NOT EXISTS idx_transfers_from ON transfers(from_addr);
-        CREATE INDEX IF NOT EXISTS idx_transfers_block ON transfers(block_number);
-
-        CREATE TABLE IF NOT EXISTS net_flow (
-            exchange TEXT NOT NULL,
-            token_address TEXT NOT NULL,
-            cumulative_amount_raw TEXT NOT NULL,
-            cumulative_amount REAL NOT NULL,
-            last_updated TEXT NOT NULL,
-            PRIMARY KEY (exchange, token_address)
-        );
+    let cli = Cli::parse();
 
-        CREATE TABLE IF NOT EXISTS metadata (
-            key TEXT PRIMARY KEY,
-            value TEXT
-        );
-        ",
-    )?;
-    println!("Schema created (or already present).");
-
-    // 3. Insert sample data
-    conn.execute_batch(
-        "
-        INSERT OR IGNORE INTO transfers (tx_hash, log_index, block_number, timestamp, from_addr, to_addr, token_address, amount_raw, amount)
-        VALUES
-        ('0xaaa0001', 0, 50000000, '2025-09-20T12:34:56Z', '0xabcdefabcdefabcdefabcdefabcdefabcdefabcd', '0xF977814e90dA44bFA03b6295A0616a897441aceC', 'POL_TOKEN_ADDRESS', '10500000000000000000', 10.5),
-        ('0xaaa0002', 0, 50000001, '2025-09-20T12:50:10Z', '0xF977814e90dA44bFA03b6295A0616a897441aceC', '0x1111222233334444555566667777888899990000', 'POL_TOKEN_ADDRESS', '2000000000000000000', 2.0),
-        ('0xaaa0003', 0, 50000002, '2025-09-20T13:12:30Z', '0x222233334444555566667777888899990000aaaa', '0x33334444555566667777888899990000bbbbaaaa', 'POL_TOKEN_ADDRESS', '750000000000000000', 0.75),
-        ('0xaaa0004', 0, 50000003, '2025-09-20T13:45:00Z', '0x555566667777888899990000aaaa111122223333', '0xD5C08681719445A5Fdce2Bda98b341A49050d821', 'POL_TOKEN_ADDRESS', '100000000000000000000', 100.0),
-        ('0xaaa0005', 0, 50000004, '2025-09-20T14:05:05Z', '0x082489A616aB4D46d1947eE3F912e080815b08DA', '0x66667777888899990000aaaabbbbccccddddeeee', 'POL_TOKEN_ADDRESS', '1000000000000000', 0.001),
-        ('0xaaa0006', 0, 50000005, '2025-09-20T14:20:20Z', '0x7777888899990000aaaabbbbccccddddeeeeffff', '0x505e71695E9bc45943c58adEC1650577BcA68fD9', 'POL_TOKEN_ADDRESS', '50000000000000000000', 50.0);
-        ",
-    )?;
-    println!("Sample data inserted.");
-
-    // 4. Compute initial net-flow
-    conn.execute_batch(
-        "
-        WITH
-        inflow AS (
-            SELECT COALESCE(SUM(amount), 0.0) AS total_in
-            FROM transfers
-            WHERE to_addr IN (
-                '0xF977814e90dA44bFA03b6295A0616a897441aceC',
-                '0xe7804c37c13166fF0b37F5aE0BB07A3aEbb6e245',
-                '0x505e71695E9bc45943c58adEC1650577BcA68fD9',
-                '0x290275e3db66394C52272398959845170E4DCb88',
-                '0xD5C08681719445A5Fdce2Bda98b341A49050d821',
-                '0x082489A616aB4D46d1947eE3F912e080815b08DA'
-            )
-        ),
-        outflow AS (
-            SELECT COALESCE(SUM(amount), 0.0) AS total_out
-            FROM transfers
-            WHERE from_addr IN (
-                '0xF977814e90dA44bFA03b6295A0616a897441aceC',
-                '0xe7804c37c13166fF0b37F5aE0BB07A3aEbb6e245',
-                '0x505e71695E9bc45943c58adEC1650577BcA68fD9',
-                '0x290275e3db66394C52272398959845170E4DCb88',
-                '0xD5C08681719445A5Fdce2Bda98b341A49050d821',
-                '0x082489A616aB4D46d1947eE3F912e080815b08DA'
-            )
-        ),
-        net AS (
-            SELECT (inflow.total_in - outflow.total_out) AS net_flow
-            FROM inflow, outflow
-        )
-        INSERT INTO net_flow (exchange, token_address, cumulative_amount_raw, cumulative_amount, last_updated)
-        VALUES ('Binance', 'POL_TOKEN_ADDRESS', '0', (SELECT net_flow FROM net), datetime('now'))
-        ON CONFLICT(exchange, token_address) DO UPDATE SET
-            cumulative_amount = excluded.cumulative_amount,
-            last_updated = excluded.last_updated;
-        ",
-    )?;
-    println!("Net-flow computed for Binance.");
-
-    // 5. RPC to fetch latest block and its transactions, filter, insert, and update net-flow
-    let rpc_url = env::var("POLYGON_RPC").expect("POLYGON_RPC must be set");
-    let client = Client::new();
-
-    // Fetch latest block number
-    let res: Value = client
-        .post(&rpc_url)
-        .json(&serde_json::json!({
-            "jsonrpc": "2.0",
-            "method": "eth_blockNumber",
-            "params": [],
-            "id": 1
-        }))
-        .timeout(Duration::from_secs(10))
-        .send()
-        .await?
-        .json()
-        .await?;
-    let block_hex = res["result"].as_str().unwrap_or("0x0");
-    let block_num = u64::from_str_radix(&block_hex[2..], 16)?;
-
-    // Fetch block details with transactions
-    let block_res: Value = client
-        .post(&rpc_url)
-        .json(&serde_json::json!({
-            "jsonrpc": "2.0",
-            "method": "eth_getBlockByNumber",
-            "params": [format!("0x{:x}", block_num), true], // true for full tx objects
-            "id": 2
-        }))
-        .timeout(Duration::from_secs(20))
-        .send()
-        .await?
-        .json()
-        .await?;
-    let empty_vec = vec![]; // Create a longer-lived empty vector
-    let transactions = block_res["result"]["transactions"].as_array().unwrap_or(&empty_vec);
-
-    // Define Binance addresses
-    let binance_addresses = vec![
-        "0xF977814e90dA44bFA03b6295A0616a897441aceC",
-        "0xe7804c37c13166fF0b37F5aE0BB07A3aEbb6e245",
-        "0x505e71695E9bc45943c58adEC1650577BcA68fD9",
-        "0x290275e3db66394C52272398959845170E4DCb88",
-        "0xD5C08681719445A5Fdce2Bda98b341A49050d821",
-        "0x082489A616aB4D46d1947eE3F912e080815b08DA",
-    ];
-
-    // Filter transactions
-    let mut binance_txs = Vec::new();
-    for tx in transactions {
-        let tx_hash = tx["hash"].as_str().unwrap_or("");
-        if let Some(from) = tx["from"].as_str() {
-            if binance_addresses.contains(&from.to_lowercase().as_str()) {
-                binance_txs.push(tx_hash);
+    // 1. Load tracked exchanges and tokens, then open the database
+    let config = Config::load(&cli.config)?;
+    println!(
+        "Loaded {} ({} exchanges, {} tokens)",
+        cli.config.display(),
+        config.exchanges.len(),
+        config.tokens.len()
+    );
+    for exchange in &config.exchanges {
+        for wallet in &exchange.wallets {
+            println!("  {} {}", exchange.name, wallet);
+        }
+    }
+
+    if cli.db.starts_with("postgres://") || cli.db.starts_with("postgresql://") {
+        let store = PgStorage::connect(&cli.db).await?;
+        println!("Connected to PostgreSQL");
+        return run(cli, config, store).await;
+    }
+    let conn = Connection::open(&cli.db).with_context(|| format!("opening {}", cli.db))?;
+    println!("Opened DB at {}", cli.db);
+    run(cli, config, conn).await
+}
+
+/// Steps 2 to 7, against either store.
+async fn run<S: Storage>(cli: Cli, config: Config, mut store: S) -> Result<()> {
+    let command = cli.command.unwrap_or(Command::Index);
+
+    // 2. Bring the schema up to date (`migrate --dry-run` only lists what that would apply)
+    if let Command::Migrate { dry_run } = command {
+        let migrations = store.migrate(dry_run).await?;
+        for migration in &migrations {
+            let verb = if dry_run { "Pending" } else { "Applied" };
+            println!("{} migration {}: {}", verb, migration.version, migration.name);
+        }
+        if dry_run {
+            println!("Dry run: {} migrations pending, nothing changed.", migrations.len());
+        } else {
+            println!("Schema at version {}.", store.schema_version().await?);
+        }
+        return Ok(());
+    }
+    // Commands that only read leave the schema alone and refuse an outdated one
+    if matches!(command, Command::Netflow | Command::Verify | Command::Export { .. }) {
+        let pending = store.migrate(true).await?;
+        if !pending.is_empty() {
+            bail!("database schema has {} pending migrations; run `migrate` first", pending.len());
+        }
+    } else {
+        for migration in store.migrate(false).await? {
+            println!("Applied migration {}: {}", migration.version, migration.name);
+        }
+    }
+
+    // 3. Commands that only need the database
+    match command {
+        Command::LoadFixtures { file } => {
+            let count = fixtures::load(&mut store, &config, &file).await?;
+            println!("Loaded {} fixture transfers from {}.", count, file.display());
+            return Ok(());
+        }
+        Command::Netflow => return print_net_flows(&mut store, &config).await,
+        Command::RebuildNetflow => {
+            // Net-flow is maintained incrementally as transfers are inserted; this recomputes
+            // it from scratch, e.g. after changing the config
+            let mismatches = store.rebuild_net_flow(&config).await?;
+            for mismatch in &mismatches {
+                println!("net_flow mismatch corrected: {}", mismatch);
             }
+            println!(
+                "Net-flow rebuilt for {} exchanges ({} mismatches).",
+                config.exchanges.len(),
+                mismatches.len()
+            );
+            return Ok(());
         }
-        if let Some(to) = tx["to"].as_str() {
-            if binance_addresses.contains(&to.to_lowercase().as_str()) {
-                binance_txs.push(tx_hash);
+        Command::Verify => {
+            let mismatches = store.check_net_flow(&config).await?;
+            for mismatch in &mismatches {
+                println!("net_flow mismatch: {}", mismatch);
+            }
+            if !mismatches.is_empty() {
+                bail!("{} net-flow figures disagree with the stored transfers; run rebuild-netflow", mismatches.len());
             }
+            println!("Net-flow matches the stored transfers.");
+            return Ok(());
+        }
+        Command::Export { from, to, output } => {
+            let count = export(&mut store, &config, from, to, &output).await?;
+            println!("Exported {} transfers to {}.", count, output.display());
+            return Ok(());
         }
+        Command::Index | Command::Backfill { .. } | Command::Migrate { .. } => {}
     }
-    println!("Binance-related txs in block {}: {:?}", block_num, binance_txs);
-
-    // Insert into transfers
-    for tx_hash in &binance_txs { // Iterate over references
-        let tx_res: Value = client
-            .post(&rpc_url)
-            .json(&serde_json::json!({
-                "jsonrpc": "2.0",
-                "method": "eth_getTransactionByHash",
-                "params": [tx_hash],
-                "id": 3
-            }))
-            .timeout(Duration::from_secs(20))
-            .send()
-            .await?
-            .json()
-            .await?;
-        let from_addr = tx_res["result"]["from"].as_str().unwrap_or("").to_string();
-        let to_addr = tx_res["result"]["to"].as_str().unwrap_or("").to_string();
-        let block_num_str = tx_res["result"]["blockNumber"].as_str().unwrap_or("0x0");
-        let block_num = u64::from_str_radix(&block_num_str[2..], 16).unwrap_or(0);
-        let timestamp = "2025-09-28T12:00:00Z".to_string(); // Placeholder
-        let amount_raw = "0".to_string(); // Placeholder
-        let amount = 0.0; // Placeholder
-
-        conn.execute(
-            "INSERT OR IGNORE INTO transfers (tx_hash, log_index, block_number, timestamp, from_addr, to_addr, token_address, amount_raw, amount)
-             VALUES (?1, 0, ?2, ?3, ?4, ?5, 'POL_TOKEN_ADDRESS', ?6, ?7)",
-            &[&tx_hash.to_string(), &block_num.to_string(), &timestamp, &from_addr, &to_addr, &amount_raw, &amount.to_string()],
-        )?;
+
+    // 4. Connect to the RPC endpoints: --rpc (or POLYGON_RPC) takes one URL or several to
+    //    fail over between
+    let rpc_urls: Vec<String> = cli
+        .rpc
+        .iter()
+        .map(|url| url.trim())
+        .filter(|url| !url.is_empty())
+        .map(String::from)
+        .collect();
+    if rpc_urls.is_empty() {
+        bail!("no RPC endpoint: pass --rpc or set POLYGON_RPC");
+    }
+    let batch_size: usize = match env::var("RPC_BATCH_SIZE") {
+        Ok(v) => v.parse()?,
+        Err(_) => rpc::DEFAULT_BATCH_SIZE,
+    };
+    let mut rpc = RpcClient::new(rpc_urls)?.with_batch_size(batch_size);
+    if let Ok(v) = env::var("RPC_MAX_RETRIES") {
+        rpc = rpc.with_retry_policy(RetryPolicy { max_retries: v.parse()?, ..RetryPolicy::default() });
+    }
+    if let Ok(v) = env::var("RPC_CU_PER_SECOND") {
+        rpc = rpc.with_rate_limit(v.parse()?).context("RPC_CU_PER_SECOND")?;
+    }
+
+    // 5. Hand everything to the indexer
+    let confirmations: u64 = match env::var("CONFIRMATIONS") {
+        Ok(v) => v.parse()?,
+        Err(_) => indexer::DEFAULT_CONFIRMATIONS,
+    };
+    let mut indexer = Indexer::new(config, store, rpc).await?.with_confirmations(confirmations);
+    // POLYGON_WS (a wss:// URL) wakes the follower as soon as a head or tracked log arrives
+    // instead of at the next poll; blocks are still fetched over HTTP.
+    if let Ok(url) = env::var("POLYGON_WS") {
+        indexer = indexer.with_websocket(url);
+    }
+
+    // 6. Backfill indexes a historical range and exits
+    if let Command::Backfill { from, to } = command {
+        let inserted = indexer.backfill(from, to).await?;
+        println!("Backfill {}..={} done: {} tracked transfers", from, to, inserted);
+        return Ok(());
     }
-    println!("Inserted {} Binance-related txs into transfers", binance_txs.len());
-
-    // Dynamic net-flow update loop
-    loop {
-        let mut stmt = conn.prepare(
-            "WITH
-             inflow AS (SELECT COALESCE(SUM(amount), 0.0) AS total_in FROM transfers WHERE to_addr IN (?, ?, ?, ?, ?, ?)),
-             outflow AS (SELECT COALESCE(SUM(amount), 0.0) AS total_out FROM transfers WHERE from_addr IN (?, ?, ?, ?, ?, ?)),
-             net AS (SELECT (inflow.total_in - outflow.total_out) AS net_flow FROM inflow, outflow)
-             INSERT INTO net_flow (exchange, token_address, cumulative_amount_raw, cumulative_amount, last_updated)
-             VALUES ('Binance', 'POL_TOKEN_ADDRESS', '0', (SELECT net_flow FROM net), datetime('now'))
-             ON CONFLICT(exchange, token_address) DO UPDATE SET
-                 cumulative_amount = excluded.cumulative_amount,
-                 last_updated = excluded.last_updated",
+
+    // 7. Otherwise follow the chain head, indexing blocks as they arrive and tracking their finality
+    indexer.follow().await
+}
+
+/// Prints one line per exchange and token: the net-flow over every indexed block and over
+/// finalized blocks only.
+async fn print_net_flows<S: Storage>(store: &mut S, config: &Config) -> Result<()> {
+    let flows = store.net_flows().await?;
+    if flows.is_empty() {
+        println!("No net-flow figures yet.");
+    }
+    for flow in flows {
+        let (symbol, decimals) = match config.token(flow.token_address) {
+            Some(token) => (token.symbol.clone(), token.decimals),
+            None => (flow.token_address.to_checksum(), 0),
+        };
+        let finalized = flow.finalized_raw.map_or("unknown".to_string(), |net| net.format_units(decimals));
+        println!(
+            "{} {}: {} (finalized {})",
+            flow.exchange,
+            symbol,
+            flow.cumulative_raw.format_units(decimals),
+            finalized
+        );
+    }
+    Ok(())
+}
+
+/// Writes the transfers of blocks `from..=to` (through the newest when `to` is `None`) to
+/// `path` as CSV and returns how many.
+async fn export<S: Storage>(store: &mut S, config: &Config, from: u64, to: Option<u64>, path: &Path) -> Result<usize> {
+    // Block numbers are stored as signed 64-bit integers.
+    let transfers = store.transfers(from, to.unwrap_or(i64::MAX as u64)).await?;
+    let file = File::create(path).with_context(|| format!("creating {}", path.display()))?;
+    let mut out = BufWriter::new(file);
+    writeln!(
+        out,
+        "block_number,timestamp,tx_hash,log_index,call_path,from_addr,to_addr,token_address,amount_raw,amount,tx_fee_raw,receipt_status"
+    )?;
+    for (transfer, block_time) in &transfers {
+        let decimals = config.token(transfer.token_address).map_or(18, |t| t.decimals);
+        let receipt = transfer.receipt;
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{},{},{},{}",
+            transfer.block_number,
+            block_time.map(iso_timestamp).transpose()?.unwrap_or_default(),
+            transfer.tx_hash,
+            transfer.log_index,
+            transfer.call_path.as_deref().unwrap_or_default(),
+            transfer.from.to_checksum(),
+            transfer.to.to_checksum(),
+            transfer.token_address.to_checksum(),
+            transfer.amount_raw,
+            format_units(transfer.amount_raw, decimals),
+            receipt.and_then(|r| r.fee_raw).map(|fee| fee.to_string()).unwrap_or_default(),
+            receipt.map(|r| (r.success as u8).to_string()).unwrap_or_default()
         )?;
-        stmt.execute(&[
-            &binance_addresses[0], &binance_addresses[1], &binance_addresses[2],
-            &binance_addresses[3], &binance_addresses[4], &binance_addresses[5],
-            &binance_addresses[0], &binance_addresses[1], &binance_addresses[2],
-            &binance_addresses[3], &binance_addresses[4], &binance_addresses[5],
-        ])?;
-        println!("Updated net_flow at {}", chrono::Utc::now());
-        tokio::time::sleep(Duration::from_secs(10)).await; // Update every 10 seconds
-    }
-    
+    }
+    out.flush()?;
+    Ok(transfers.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn cli_is_well_formed() {
+        Cli::command().debug_assert();
+    }
+
+    #[tokio::test]
+    async fn read_only_commands_refuse_an_outdated_schema() {
+        let config = Config::load("config.toml").unwrap();
+        let path = env::temp_dir().join(format!("read-only-{}.db", std::process::id()));
+        for args in [["indexer", "netflow"], ["indexer", "verify"], ["indexer", "export"]] {
+            let conn = Connection::open(&path).unwrap();
+            let err = run(Cli::parse_from(args), config.clone(), conn).await.unwrap_err();
+            assert!(err.to_string().contains("pending migrations"), "{}", err);
+        }
+        let mut conn = Connection::open(&path).unwrap();
+        assert_eq!(conn.schema_version().await.unwrap(), 0);
+        drop(conn);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn exports_transfers_as_csv() {
+        let config = Config::load("config.toml").unwrap();
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.migrate(false).await.unwrap();
+        let count = fixtures::load(&mut conn, &config, fixtures::SAMPLE_TRANSFERS).await.unwrap();
+        let path = env::temp_dir().join(format!("export-{}.csv", std::process::id()));
+
+        // Without `to` everything from `from` on is exported.
+        assert_eq!(export(&mut conn, &config, 0, None, &path).await.unwrap(), count);
+        let csv = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), count + 1);
+        assert!(lines[0].starts_with("block_number,timestamp,tx_hash,log_index,"));
+        assert_eq!(
+            lines[1],
+            "50000000,2025-09-20T12:34:56Z,0xaaa0001,0,,0xABcdEFABcdEFabcdEfAbCdefabcdeFABcDEFabCD,\
+             0xF977814e90dA44bFA03b6295A0616a897441aceC,0xEeeeeEeeeEeEeeEeEeEeeEEEeeeeEeeeeeeeEEeE,\
+             10500000000000000000,10.5,,"
+        );
+
+        assert_eq!(export(&mut conn, &config, 50000001, Some(50000002), &path).await.unwrap(), 2);
+        let csv = std::fs::read_to_string(&path).unwrap();
+        let blocks: Vec<&str> = csv.lines().skip(1).map(|line| line.split(',').next().unwrap()).collect();
+        assert_eq!(blocks, ["50000001", "50000002"]);
+        std::fs::remove_file(&path).unwrap();
+    }
 }
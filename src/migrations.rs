@@ -0,0 +1,141 @@
+use anyhow::{bail, Result}; // Error handling
+
+/// One schema change. Each store records the version of the last one it applied in
+/// `metadata` and applies the newer ones in order, each in its own transaction. Never edit
+/// a released migration; add a new one.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// SQLite migrations, oldest first.
+pub const SQLITE: &[Migration] = &[
+    Migration { version: 1, name: "initial schema", sql: include_str!("../sql/polschema.sql") },
+    Migration {
+        version: 2,
+        name: "lowercase stored addresses",
+        // Databases from before addresses were normalized may hold checksummed ones.
+        sql: "UPDATE transfers SET from_addr = lower(from_addr), to_addr = lower(to_addr), token_address = lower(token_address)
+              WHERE from_addr <> lower(from_addr) OR to_addr <> lower(to_addr) OR token_address <> lower(token_address);
+              UPDATE net_flow SET token_address = lower(token_address) WHERE token_address <> lower(token_address);
+              UPDATE net_flow_history SET token_address = lower(token_address) WHERE token_address <> lower(token_address);",
+    },
+    Migration {
+        version: 3,
+        name: "drop placeholder rows",
+        // The sample database shipped with placeholder transfers (token 'POL_TOKEN_ADDRESS',
+        // tx hashes like 0xaaa0001) that no real chain data can match, and a net_flow row
+        // summing them. Nothing reading a token address can parse them.
+        sql: "DELETE FROM transfers
+              WHERE length(token_address) <> 42 OR substr(token_address, 1, 2) <> '0x' OR substr(token_address, 3) GLOB '*[^0-9a-f]*';
+              DELETE FROM net_flow
+              WHERE length(token_address) <> 42 OR substr(token_address, 1, 2) <> '0x' OR substr(token_address, 3) GLOB '*[^0-9a-f]*';
+              DELETE FROM net_flow_history
+              WHERE length(token_address) <> 42 OR substr(token_address, 1, 2) <> '0x' OR substr(token_address, 3) GLOB '*[^0-9a-f]*';",
+    },
+];
+
+/// PostgreSQL migrations, oldest first.
+pub const POSTGRES: &[Migration] = &[
+    Migration { version: 1, name: "initial schema", sql: include_str!("../sql/pgschema.sql") },
+];
+
+/// The migrations newer than `current`. Fails when `current` is newer than any of them:
+/// the database was migrated by a later build and this one must not write to it.
+pub fn pending(migrations: &'static [Migration], current: u32) -> Result<&'static [Migration]> {
+    let latest = migrations.last().map_or(0, |m| m.version);
+    if current > latest {
+        bail!(
+            "database schema version {} is newer than the latest this build knows ({}); upgrade the indexer",
+            current,
+            latest
+        );
+    }
+    Ok(&migrations[migrations.partition_point(|m| m.version <= current)..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::db;
+    use crate::storage::Storage;
+    use rusqlite::Connection;
+
+    #[test]
+    fn versions_count_up_from_one() {
+        for migrations in [SQLITE, POSTGRES] {
+            for (i, migration) in migrations.iter().enumerate() {
+                assert_eq!(migration.version as usize, i + 1, "{}", migration.name);
+            }
+        }
+    }
+
+    #[test]
+    fn applies_pending_migrations_once_and_refuses_newer_schemas() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert_eq!(db::schema_version(&conn).unwrap(), 0);
+
+        // A dry run lists everything and changes nothing.
+        assert_eq!(db::migrate(&mut conn, true).unwrap().len(), SQLITE.len());
+        assert_eq!(db::schema_version(&conn).unwrap(), 0);
+
+        assert_eq!(db::migrate(&mut conn, false).unwrap().len(), SQLITE.len());
+        assert_eq!(db::schema_version(&conn).unwrap(), SQLITE.len() as u32);
+        assert!(db::migrate(&mut conn, false).unwrap().is_empty());
+
+        conn.execute("UPDATE metadata SET value = '99' WHERE key = ?1", [db::SCHEMA_VERSION]).unwrap();
+        let err = db::migrate(&mut conn, true).unwrap_err();
+        assert!(err.to_string().contains("version 99 is newer"), "{}", err);
+    }
+
+    #[test]
+    fn upgrades_databases_from_before_versioning() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE transfers (tx_hash TEXT NOT NULL, log_index INTEGER NOT NULL, block_number INTEGER NOT NULL,
+                 timestamp TEXT NOT NULL, from_addr TEXT NOT NULL, to_addr TEXT NOT NULL, token_address TEXT NOT NULL,
+                 amount_raw TEXT NOT NULL, amount REAL NOT NULL, tx_fee_raw TEXT, receipt_status INTEGER,
+                 PRIMARY KEY (tx_hash, log_index));
+             CREATE TABLE metadata (key TEXT PRIMARY KEY, value TEXT);
+             INSERT INTO metadata (key, value) VALUES ('last_indexed_block', '7');
+             INSERT INTO transfers VALUES ('0x1', 0, 7, '2025-09-20T12:34:56Z', '0xABCDEFABCDEFABCDEFABCDEFABCDEFABCDEFABCD',
+                 '0xf977814e90da44bfa03b6295a0616a897441acec', '0xeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee', '1', 1.0, NULL, NULL);",
+        )
+        .unwrap();
+
+        db::migrate(&mut conn, false).unwrap();
+
+        let (from, unix): (String, i64) = conn
+            .query_row("SELECT from_addr, timestamp_unix FROM transfers", [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        assert_eq!(from, "0xabcdefabcdefabcdefabcdefabcdefabcdefabcd");
+        assert_eq!(unix, 1758371696);
+        assert_eq!(db::last_indexed_block(&conn).unwrap(), Some(7));
+        assert_eq!(db::schema_version(&conn).unwrap(), SQLITE.len() as u32);
+    }
+
+    #[tokio::test]
+    async fn upgrades_the_shipped_database() {
+        // On a copy: the tracked file stays at its original schema.
+        let path = std::env::temp_dir().join(format!("migrations-shipped-{}.db", std::process::id()));
+        std::fs::copy(concat!(env!("CARGO_MANIFEST_DIR"), "/data/polygon.db"), &path).unwrap();
+        let mut conn = Connection::open(&path).unwrap();
+        let config = Config::load(concat!(env!("CARGO_MANIFEST_DIR"), "/config.toml")).unwrap();
+
+        db::migrate(&mut conn, false).unwrap();
+
+        let placeholders: i64 = conn
+            .query_row("SELECT COUNT(*) FROM transfers WHERE token_address NOT LIKE '0x%'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(placeholders, 0);
+        // These parse every stored token address and failed on the placeholders.
+        conn.net_flows().await.unwrap();
+        conn.transfers(0, i64::MAX as u64).await.unwrap();
+        assert!(conn.check_net_flow(&config).await.unwrap().is_empty());
+        drop(conn);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,459 @@
+use anyhow::{anyhow, Result}; // Error handling
+use chrono::Utc; // For last_updated
+use ethnum::U256; // Exact 256-bit token amounts
+use std::collections::HashMap;
+
+use crate::address::Address;
+use crate::amount::{self, NetAmount};
+use crate::config::{Config, Token};
+use crate::db;
+use crate::decode::Transfer;
+use crate::history;
+use crate::sql::Sql;
+
+/// Maps wallet and token addresses to their position in the config.
+pub struct FlowIndex<'a> {
+    config: &'a Config,
+    wallets: HashMap<Address, usize>,
+    tokens: HashMap<Address, usize>,
+}
+
+impl<'a> FlowIndex<'a> {
+    pub fn new(config: &'a Config) -> Self {
+        let wallets = config
+            .exchanges
+            .iter()
+            .enumerate()
+            .flat_map(|(i, e)| e.wallets.iter().map(move |w| (w.address, i)))
+            .collect();
+        let tokens = config
+            .tokens
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (t.address, i))
+            .collect();
+        Self { config, wallets, tokens }
+    }
+
+    /// Token index plus sending and receiving exchange of a transfer, or `None` when it
+    /// does not move a tracked token between exchanges. A transfer between wallets of two
+    /// different exchanges is an outflow for the sender's exchange and an inflow for the
+    /// receiver's; moves between wallets of the same exchange cancel out.
+    fn classify(&self, from: Address, to: Address, token_address: Address) -> Option<(usize, Option<usize>, Option<usize>)> {
+        let token = *self.tokens.get(&token_address)?;
+        let from = self.wallets.get(&from).copied();
+        let to = self.wallets.get(&to).copied();
+        if from == to {
+            return None;
+        }
+        Some((token, from, to))
+    }
+
+    /// The `(exchange, token, inflow)` legs of a transfer: an inflow for the receiving
+    /// exchange and an outflow for the sending one. Empty when it moves no tracked token
+    /// between exchanges.
+    pub fn legs(&self, from: Address, to: Address, token_address: Address) -> Vec<(&'a str, &'a Token, bool)> {
+        let Some((token, from, to)) = self.classify(from, to, token_address) else {
+            return Vec::new();
+        };
+        let token = &self.config.tokens[token];
+        let mut legs = Vec::new();
+        if let Some(exchange) = to {
+            legs.push((self.config.exchanges[exchange].name.as_str(), token, true));
+        }
+        if let Some(exchange) = from {
+            legs.push((self.config.exchanges[exchange].name.as_str(), token, false));
+        }
+        legs
+    }
+}
+
+/// One transfer as seen by the net-flow bookkeeping.
+pub struct Flow {
+    pub from: Address,
+    pub to: Address,
+    pub token_address: Address,
+    pub raw: U256,
+    pub block_number: u64,
+    /// Unix seconds, `None` for legacy rows stored without one.
+    pub block_time: Option<i64>,
+}
+
+impl Flow {
+    pub fn of(transfer: &Transfer, block_time: i64) -> Self {
+        Self {
+            from: transfer.from,
+            to: transfer.to,
+            token_address: transfer.token_address,
+            raw: transfer.amount_raw,
+            block_number: transfer.block_number,
+            block_time: Some(block_time),
+        }
+    }
+}
+
+/// Transfers that count towards net-flow: all but those of failed transactions. Rows
+/// without a receipt (`receipt_status` NULL) are assumed to have succeeded.
+const COUNTED: &str = "(receipt_status IS NULL OR receipt_status <> 0)";
+
+/// Inserts transfers, each with its token's decimals and block time, and folds the new ones
+/// into `net_flow` within the caller's transaction. Transfers already present (same
+/// `(tx_hash, log_index)`) are not counted twice, and those of failed transactions are
+/// stored but not counted at all.
+pub async fn insert_transfers(conn: &mut impl Sql, config: &Config, rows: &[(&Transfer, u32, i64)]) -> Result<()> {
+    let index = FlowIndex::new(config);
+    for &(transfer, decimals, block_time) in rows {
+        if insert_transfer(conn, transfer, block_time, decimals).await? && transfer.counts_towards_net_flow() {
+            apply_transfer(conn, &index, &Flow::of(transfer, block_time), false).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Inserts one row per log; re-inserting the same `(tx_hash, log_index)` is a no-op.
+/// Returns whether a new row was written. `tx_fee_raw` and `receipt_status` stay NULL
+/// when the transfer carries no receipt.
+/// `block_time` is the block's unix timestamp, also stored as ISO-8601 UTC; `decimals`
+/// are the token's, used to derive the `amount` column.
+async fn insert_transfer(conn: &mut impl Sql, transfer: &Transfer, block_time: i64, decimals: u32) -> Result<bool> {
+    let inserted = conn
+        .execute(
+            "INSERT INTO transfers (tx_hash, log_index, call_path, block_number, timestamp, timestamp_unix, from_addr, to_addr, token_address, amount_raw, amount, tx_fee_raw, receipt_status)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+             ON CONFLICT (tx_hash, log_index) DO NOTHING",
+            &[
+                transfer.tx_hash.as_str().into(),
+                transfer.log_index.into(),
+                transfer.call_path.clone().into(),
+                (transfer.block_number as i64).into(),
+                db::iso_timestamp(block_time)?.into(),
+                block_time.into(),
+                transfer.from.to_lower_hex().into(),
+                transfer.to.to_lower_hex().into(),
+                transfer.token_address.to_lower_hex().into(),
+                transfer.amount_raw.to_string().into(),
+                transfer.amount(decimals).into(),
+                transfer.receipt.and_then(|r| r.fee_raw).map(|fee| fee.to_string()).into(),
+                transfer.receipt.map(|r| r.success as i64).into(),
+            ],
+        )
+        .await?;
+    Ok(inserted == 1)
+}
+
+/// Folds one newly inserted transfer into the stored `net_flow` rows and the
+/// `net_flow_history` buckets. `revert` takes a previously applied transfer back out, e.g.
+/// when its block is orphaned by a reorg. Runs inside the caller's transaction, so rows and
+/// transfers never disagree.
+pub async fn apply_transfer(conn: &mut impl Sql, index: &FlowIndex<'_>, flow: &Flow, revert: bool) -> Result<()> {
+    for (exchange, token, inflow) in index.legs(flow.from, flow.to, flow.token_address) {
+        adjust(conn, exchange, token, flow.raw, inflow != revert).await?;
+        history::record(conn, exchange, token, flow, inflow, !revert).await?;
+    }
+    Ok(())
+}
+
+async fn adjust(conn: &mut impl Sql, exchange: &str, token: &Token, raw: U256, add: bool) -> Result<()> {
+    let mut net = stored(conn, exchange, &token.address).await?.unwrap_or_default();
+    if add {
+        net.add(raw)?;
+    } else {
+        net.sub(raw)?;
+    }
+    upsert(conn, exchange, &token.address, net, token.decimals).await
+}
+
+/// Takes every counted transfer above `block` back out of `net_flow` and its history,
+/// before those rows are deleted.
+pub async fn revert_above(conn: &mut impl Sql, index: &FlowIndex<'_>, block: u64) -> Result<()> {
+    replay(conn, index, Some(block), true).await
+}
+
+/// Applies (or reverts) every counted transfer, or only those above `above`.
+async fn replay(conn: &mut impl Sql, index: &FlowIndex<'_>, above: Option<u64>, revert: bool) -> Result<()> {
+    let rows = conn
+        .query(
+            &format!(
+                "SELECT from_addr, to_addr, token_address, amount_raw, block_number, timestamp_unix FROM transfers
+                 WHERE {} AND block_number > ?1",
+                COUNTED
+            ),
+            &[above.map_or(-1, |block| block as i64).into()],
+        )
+        .await?;
+    for row in rows {
+        let flow = Flow {
+            from: row[0].as_str()?.parse()?,
+            to: row[1].as_str()?.parse()?,
+            token_address: row[2].as_str()?.parse()?,
+            raw: amount::parse_raw(row[3].as_str()?)?,
+            block_number: row[4].as_i64()? as u64,
+            block_time: row[5].as_opt_i64()?,
+        };
+        apply_transfer(conn, index, &flow, revert).await?;
+    }
+    Ok(())
+}
+
+/// The `cumulative_amount_raw` of a pair, `None` when it has no row yet.
+async fn stored(conn: &mut impl Sql, exchange: &str, token_address: &Address) -> Result<Option<NetAmount>> {
+    let row = conn
+        .query_opt(
+            "SELECT cumulative_amount_raw FROM net_flow WHERE exchange = ?1 AND token_address = ?2",
+            &[exchange.into(), token_address.to_lower_hex().into()],
+        )
+        .await?;
+    row.map(|row| NetAmount::parse(row[0].as_str()?)).transpose()
+}
+
+/// Sets the finalized columns of every `net_flow` row: the tip figure minus the counted
+/// transfers above `finalized`, which a reorg could still remove. `None` clears them.
+pub async fn refresh_finalized(conn: &mut impl Sql, config: &Config, finalized: Option<u64>) -> Result<()> {
+    let Some(finalized) = finalized else {
+        conn.execute("UPDATE net_flow SET finalized_amount_raw = NULL, finalized_amount = NULL", &[])
+            .await?;
+        return Ok(());
+    };
+    let unfinalized = compute(conn, config, Some(finalized)).await?;
+    for (exchange, row) in config.exchanges.iter().zip(&unfinalized) {
+        for (token, pending) in config.tokens.iter().zip(row) {
+            let tip = match stored(conn, &exchange.name, &token.address).await? {
+                Some(tip) => tip,
+                None => {
+                    upsert(conn, &exchange.name, &token.address, NetAmount::default(), token.decimals).await?;
+                    NetAmount::default()
+                }
+            };
+            let net = NetAmount(tip.0.checked_sub(pending.0).ok_or_else(|| anyhow!("net amount overflow"))?);
+            conn.execute(
+                "UPDATE net_flow SET finalized_amount_raw = ?3, finalized_amount = ?4
+                 WHERE exchange = ?1 AND token_address = ?2",
+                &[
+                    exchange.name.as_str().into(),
+                    token.address.to_lower_hex().into(),
+                    net.0.to_string().into(),
+                    net.to_units(token.decimals).into(),
+                ],
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn upsert(conn: &mut impl Sql, exchange: &str, token_address: &Address, net: NetAmount, decimals: u32) -> Result<()> {
+    conn.execute(
+        "INSERT INTO net_flow (exchange, token_address, cumulative_amount_raw, cumulative_amount, last_updated)
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT (exchange, token_address) DO UPDATE SET
+             cumulative_amount_raw = excluded.cumulative_amount_raw,
+             cumulative_amount = excluded.cumulative_amount,
+             last_updated = excluded.last_updated",
+        &[
+            exchange.into(),
+            token_address.to_lower_hex().into(),
+            net.0.to_string().into(),
+            net.to_units(decimals).into(),
+            // The format of SQLite's `datetime('now')`.
+            Utc::now().format("%Y-%m-%d %H:%M:%S").to_string().into(),
+        ],
+    )
+    .await?;
+    Ok(())
+}
+
+/// Recomputes every configured exchange and token from scratch over all counted `transfers` and
+/// replaces the `net_flow` and `net_flow_history` tables with the result. Returns a description of each pair whose
+/// incrementally maintained value differed, for verification. Callers wrap this in a
+/// transaction.
+pub async fn rebuild(conn: &mut impl Sql, config: &Config) -> Result<Vec<String>> {
+    let flows = compute_all(conn, config).await?;
+    let mismatches = mismatches(conn, config, &flows).await?;
+
+    conn.execute("DELETE FROM net_flow", &[]).await?;
+    conn.execute("DELETE FROM net_flow_history", &[]).await?;
+    replay(conn, &FlowIndex::new(config), None, false).await?;
+    // Pairs without any transfer still get a zero row.
+    for (exchange, row) in config.exchanges.iter().zip(&flows) {
+        for (token, net) in config.tokens.iter().zip(row) {
+            upsert(conn, &exchange.name, &token.address, *net, token.decimals).await?;
+        }
+    }
+    let finalized = conn
+        .query_opt("SELECT value FROM metadata WHERE key = ?1", &[db::FINALIZED_BLOCK.into()])
+        .await?
+        .map(|row| row[0].as_str()?.parse::<u64>().map_err(anyhow::Error::from))
+        .transpose()?;
+    refresh_finalized(conn, config, finalized).await?;
+    Ok(mismatches)
+}
+
+/// Describes each pair whose stored figure differs from the recomputed `flows`.
+async fn mismatches(conn: &mut impl Sql, config: &Config, flows: &[Vec<NetAmount>]) -> Result<Vec<String>> {
+    let mut mismatches = Vec::new();
+    for (exchange, row) in config.exchanges.iter().zip(flows) {
+        for (token, net) in config.tokens.iter().zip(row) {
+            let stored = stored(conn, &exchange.name, &token.address).await?.unwrap_or_default();
+            if stored != *net {
+                mismatches.push(format!(
+                    "{} {}: stored {}, recomputed {}",
+                    exchange.name, token.symbol, stored.0, net.0
+                ));
+            }
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Exact signed net-flows in raw token units, indexed `[exchange][token]` in config order,
+/// computed in a single pass over `transfers`.
+pub async fn compute_all(conn: &mut impl Sql, config: &Config) -> Result<Vec<Vec<NetAmount>>> {
+    compute(conn, config, None).await
+}
+
+/// Like `compute_all`, over the counted transfers above `above` only.
+async fn compute(conn: &mut impl Sql, config: &Config, above: Option<u64>) -> Result<Vec<Vec<NetAmount>>> {
+    let rows = conn
+        .query(
+            &format!(
+                "SELECT from_addr, to_addr, token_address, amount_raw FROM transfers WHERE {} AND block_number > ?1",
+                COUNTED
+            ),
+            &[above.map_or(-1, |block| block as i64).into()],
+        )
+        .await?;
+    accumulate(
+        config,
+        rows.iter().map(|row| {
+            Ok((
+                row[0].as_str()?.parse()?,
+                row[1].as_str()?.parse()?,
+                row[2].as_str()?.parse()?,
+                amount::parse_raw(row[3].as_str()?)?,
+            ))
+        }),
+    )
+}
+
+/// Net-flows indexed `[exchange][token]` in config order over `(from, to, token, amount)`
+/// transfers.
+pub fn accumulate(
+    config: &Config,
+    transfers: impl IntoIterator<Item = Result<(Address, Address, Address, U256)>>,
+) -> Result<Vec<Vec<NetAmount>>> {
+    let index = FlowIndex::new(config);
+    let mut flows = vec![vec![NetAmount::default(); config.tokens.len()]; config.exchanges.len()];
+    for transfer in transfers {
+        let (from, to, token_address, raw) = transfer?;
+        let Some((token, from, to)) = index.classify(from, to, token_address) else {
+            continue;
+        };
+        if let Some(exchange) = to {
+            flows[exchange][token].add(raw)?;
+        }
+        if let Some(exchange) = from {
+            flows[exchange][token].sub(raw)?;
+        }
+    }
+    Ok(flows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Exchange, Wallet};
+    use crate::decode::Receipt;
+    use ethnum::I256;
+    use crate::testutil::{memory_db, test_config, BINANCE, OTHER, POL_TOKEN_ADDRESS};
+    use rusqlite::{params, Connection};
+
+    const COINBASE: &str = "0x2222222222222222222222222222222222222222";
+    const BINANCE_COLD: &str = "0x3333333333333333333333333333333333333333";
+
+    fn insert(conn: &Connection, tag: u32, from: &str, to: &str, raw: &str) {
+        conn.execute(
+            "INSERT INTO transfers (tx_hash, log_index, block_number, timestamp, from_addr, to_addr, token_address, amount_raw, amount)
+             VALUES (?1, 0, 1, '2025-01-01T00:00:00Z', ?2, ?3, ?4, ?5, 0)",
+            params![format!("0x{:x}", tag), from, to, POL_TOKEN_ADDRESS, raw],
+        )
+        .unwrap();
+    }
+
+    fn transfer(tag: u32, block_number: u64, from: &str, to: &str, raw: u128, success: bool) -> Transfer {
+        Transfer {
+            tx_hash: format!("0x{:064x}", tag),
+            log_index: 0,
+            call_path: None,
+            block_number,
+            token_address: POL_TOKEN_ADDRESS.parse().unwrap(),
+            from: from.parse().unwrap(),
+            to: to.parse().unwrap(),
+            amount_raw: U256::from(raw),
+            receipt: Some(Receipt { success, fee_raw: None }),
+        }
+    }
+
+    fn binance_raw(conn: &Connection) -> String {
+        conn.query_row(
+            "SELECT cumulative_amount_raw FROM net_flow WHERE exchange = 'Binance' AND token_address = ?1",
+            [POL_TOKEN_ADDRESS],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn incremental_updates_match_a_rebuild_and_rebuild_corrects_drift() {
+        let config = test_config();
+        let mut conn = memory_db();
+        let inflow = transfer(1, 1, OTHER, BINANCE, 10, true);
+        let outflow = transfer(2, 2, BINANCE, OTHER, 3, true);
+        let failed = transfer(3, 3, OTHER, BINANCE, 100, false);
+        insert_transfers(&mut conn, &config, &[(&inflow, 18, 0), (&outflow, 18, 2)]).await.unwrap();
+        // A re-delivered transfer is not counted twice, a failed one not at all.
+        insert_transfers(&mut conn, &config, &[(&outflow, 18, 2), (&failed, 18, 4)]).await.unwrap();
+        assert_eq!(binance_raw(&conn), "7");
+        assert_eq!(compute_all(&mut conn, &config).await.unwrap(), vec![vec![NetAmount(I256::new(7)), NetAmount::default()]]);
+        assert!(rebuild(&mut conn, &config).await.unwrap().is_empty());
+        assert_eq!(binance_raw(&conn), "7");
+
+        conn.execute("UPDATE net_flow SET cumulative_amount_raw = '1' WHERE token_address = ?1", [POL_TOKEN_ADDRESS])
+            .unwrap();
+        let mismatches = rebuild(&mut conn, &config).await.unwrap();
+        assert_eq!(mismatches.len(), 1);
+        assert!(mismatches[0].starts_with("Binance") && mismatches[0].contains("stored 1, recomputed 7"), "{:?}", mismatches);
+        assert_eq!(binance_raw(&conn), "7");
+
+        // Reverting the blocks above 1 leaves the first inflow.
+        revert_above(&mut conn, &FlowIndex::new(&config), 1).await.unwrap();
+        assert_eq!(binance_raw(&conn), "10");
+    }
+
+    #[tokio::test]
+    async fn transfers_between_exchanges_move_flow_from_one_to_the_other() {
+        let mut config = test_config();
+        config.exchanges[0].wallets.push(Wallet { address: BINANCE_COLD.parse().unwrap(), label: None, kind: None });
+        config.exchanges.push(Exchange {
+            name: "Coinbase".into(),
+            wallets: vec![Wallet { address: COINBASE.parse().unwrap(), label: None, kind: None }],
+        });
+        let mut conn = memory_db();
+        insert(&conn, 1, OTHER, BINANCE, "10");
+        insert(&conn, 2, BINANCE, COINBASE, "4");
+        insert(&conn, 3, BINANCE, BINANCE_COLD, "5");
+        insert(&conn, 4, COINBASE, OTHER, "1");
+
+        rebuild(&mut conn, &config).await.unwrap();
+
+        let raw = |exchange: &str| -> String {
+            conn.query_row(
+                "SELECT cumulative_amount_raw FROM net_flow WHERE exchange = ?1 AND token_address = ?2",
+                params![exchange, POL_TOKEN_ADDRESS],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(raw("Binance"), "6");
+        assert_eq!(raw("Coinbase"), "3");
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::Result; // Error handling
+use serde_json::{json, Value}; // Parses JSON
+use std::fmt;
+use tiny_keccak::{Hasher, Keccak}; // Digests log sets for comparison
+
+use crate::config::{Config, Quorum};
+use crate::rpc::{BlockHeader, RpcClient};
+
+/// Endpoints that did not confirm a block, for the logs and the quarantine table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Disagreement {
+    /// What was compared: `"hash"` or `"logs"`.
+    pub kind: &'static str,
+    /// Endpoints that agreed with the block as fetched, the fetching one included.
+    pub agreeing: usize,
+    /// Each endpoint's name and answer, in configuration order.
+    pub answers: Vec<(String, String)>,
+}
+
+impl Disagreement {
+    /// The answers as a JSON object keyed by endpoint.
+    pub fn answers_json(&self) -> String {
+        let map: serde_json::Map<String, Value> =
+            self.answers.iter().map(|(name, answer)| (name.clone(), json!(answer))).collect();
+        Value::Object(map).to_string()
+    }
+}
+
+impl fmt::Display for Disagreement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} agreed on the {}", self.agreeing, self.kind)?;
+        for (name, answer) in &self.answers {
+            write!(f, "; {} says {}", name, answer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Asks every endpoint for the hash of `header`'s height and, when `quorum.check_logs` is
+/// set, for the tracked logs of that hash. Returns the first check that fewer than
+/// `quorum.agree` endpoints passed, or `None` when the block can be committed. A minority
+/// that disagrees is only logged.
+pub async fn verify(
+    rpc: &RpcClient,
+    quorum: &Quorum,
+    header: &BlockHeader,
+    logs: &[Value],
+    config: &Config,
+) -> Result<Option<Disagreement>> {
+    let names = rpc.endpoint_names();
+    let hashes = rpc
+        .ask_all("eth_getBlockByNumber", json!([format!("0x{:x}", header.number), false]))
+        .await
+        .into_iter()
+        .map(|answer| match answer {
+            Ok(block) => match block["hash"].as_str() {
+                Some(hash) => hash.to_lowercase(),
+                None => "no block".to_string(),
+            },
+            Err(err) => format!("error: {}", err),
+        })
+        .collect();
+    if let Some(disagreement) = tally("hash", &names, hashes, &header.hash.to_lowercase(), quorum, header.number) {
+        return Ok(Some(disagreement));
+    }
+    if !quorum.check_logs {
+        return Ok(None);
+    }
+
+    let (addresses, topics) = config.log_filter();
+    let digests = rpc
+        .ask_all("eth_getLogs", json!([{ "blockHash": header.hash, "address": addresses, "topics": [topics] }]))
+        .await
+        .into_iter()
+        .map(|answer| match answer {
+            Ok(Value::Array(logs)) => digest(&logs),
+            Ok(other) => format!("non-array result {}", other),
+            Err(err) => format!("error: {}", err),
+        })
+        .collect();
+    Ok(tally("logs", &names, digests, &digest(logs), quorum, header.number))
+}
+
+/// Counts the endpoints whose answer equals `expected`, logging any that differ.
+fn tally(
+    kind: &'static str,
+    names: &[&str],
+    answers: Vec<String>,
+    expected: &str,
+    quorum: &Quorum,
+    number: u64,
+) -> Option<Disagreement> {
+    let agreeing = answers.iter().filter(|a| *a == expected).count();
+    if agreeing == answers.len() {
+        return None;
+    }
+    let disagreement = Disagreement {
+        kind,
+        agreeing,
+        answers: names.iter().map(|n| n.to_string()).zip(answers).collect(),
+    };
+    if agreeing >= quorum.agree {
+        println!("Block {}: endpoints disagree, quorum of {} reached: {}", number, quorum.agree, disagreement);
+        return None;
+    }
+    Some(disagreement)
+}
+
+/// Order-independent summary of a log set: its size and a short keccak digest of the
+/// sorted `(transaction, index, address, topics, data)` tuples.
+fn digest(logs: &[Value]) -> String {
+    let mut entries: Vec<String> = logs
+        .iter()
+        .map(|log| {
+            format!(
+                "{}:{}:{}:{}:{}",
+                log["transactionHash"].as_str().unwrap_or_default(),
+                log["logIndex"].as_str().unwrap_or_default(),
+                log["address"].as_str().unwrap_or_default(),
+                log["topics"],
+                log["data"].as_str().unwrap_or_default()
+            )
+            .to_lowercase()
+        })
+        .collect();
+    entries.sort_unstable();
+    let mut hash = [0u8; 32];
+    let mut keccak = Keccak::v256();
+    for entry in &entries {
+        keccak.update(entry.as_bytes());
+        keccak.update(b"\n");
+    }
+    keccak.finalize(&mut hash);
+    let short: String = hash[..4].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{} logs, digest {}", entries.len(), short)
+}
@@ -0,0 +1,111 @@
+use anyhow::{bail, Result}; // Error handling
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex; // Shared between concurrent requests
+
+/// Compute units a provider charges per call, after Alchemy's published table; unknown
+/// methods are charged like an `eth_getLogs`.
+pub fn compute_units(method: &str) -> f64 {
+    match method {
+        "eth_blockNumber" | "eth_chainId" => 10.0,
+        "eth_getTransactionReceipt" => 15.0,
+        "eth_getBlockByNumber" | "eth_getBlockByHash" => 16.0,
+        "eth_getLogs" => 75.0,
+        "trace_block" => 24.0,
+        "debug_traceBlockByHash" | "debug_traceBlockByNumber" => 309.0,
+        "eth_getBlockReceipts" => 500.0,
+        _ => 75.0,
+    }
+}
+
+/// Client-side token bucket holding up to `capacity` compute units, refilled at
+/// `per_second`; a call waits until its cost is available.
+pub struct TokenBucket {
+    state: Mutex<Bucket>,
+}
+
+struct Bucket {
+    capacity: f64,
+    per_second: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket allowing bursts of one second's budget. Fails unless `per_second` is
+    /// positive and finite.
+    pub fn new(per_second: f64) -> Result<Self> {
+        if !(per_second.is_finite() && per_second > 0.0) {
+            bail!("compute units per second must be a positive number, got {}", per_second);
+        }
+        Ok(Self { state: Mutex::new(Bucket::new(per_second, per_second, Instant::now())) })
+    }
+
+    /// Waits until `cost` compute units are available and takes them.
+    pub async fn acquire(&self, cost: f64) {
+        let wait = self.state.lock().await.reserve(cost, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+impl Bucket {
+    fn new(capacity: f64, per_second: f64, now: Instant) -> Self {
+        Self { capacity, per_second, tokens: capacity, updated: now }
+    }
+
+    /// Takes `cost` tokens, going into debt if needed, and returns how long the caller must
+    /// wait for the debt to be refilled. Later callers queue behind that debt, so the
+    /// long-run rate never exceeds `per_second`. A cost above `capacity`, e.g. a large
+    /// batch, is charged in full and waits out the excess.
+    fn reserve(&mut self, cost: f64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.capacity);
+        self.updated = now;
+        self.tokens -= cost;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.per_second)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_allows_a_burst_then_paces_to_the_refill_rate() {
+        let start = Instant::now();
+        let mut bucket = Bucket::new(100.0, 50.0, start);
+        assert_eq!(bucket.reserve(60.0, start), Duration::ZERO);
+        assert_eq!(bucket.reserve(40.0, start), Duration::ZERO);
+        // Empty: the next 25 units take half a second at 50/s, and a caller right behind
+        // it queues for another half second.
+        assert_eq!(bucket.reserve(25.0, start), Duration::from_millis(500));
+        assert_eq!(bucket.reserve(25.0, start), Duration::from_secs(1));
+        // Two seconds of refill repay the 50-unit debt and bank another 50.
+        assert_eq!(bucket.reserve(0.0, start + Duration::from_secs(2)), Duration::ZERO);
+        assert_eq!(bucket.tokens, 50.0);
+    }
+
+    #[test]
+    fn charges_costs_above_capacity_in_full() {
+        let start = Instant::now();
+        let mut bucket = Bucket::new(100.0, 50.0, start);
+        // A 250-unit batch from a full bucket leaves 150 units of debt: three seconds.
+        assert_eq!(bucket.reserve(250.0, start), Duration::from_secs(3));
+        assert_eq!(bucket.reserve(0.0, start + Duration::from_secs(1)), Duration::from_secs(2));
+        assert_eq!(bucket.reserve(0.0, start + Duration::from_secs(3)), Duration::ZERO);
+        assert_eq!(bucket.tokens, 0.0);
+    }
+
+    #[test]
+    fn rejects_rates_that_are_not_positive() {
+        for rate in [0.0, -5.0, f64::NAN, f64::INFINITY] {
+            assert!(TokenBucket::new(rate).is_err(), "{}", rate);
+        }
+        assert!(TokenBucket::new(0.5).is_ok());
+    }
+}
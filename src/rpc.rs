@@ -0,0 +1,698 @@
+use anyhow::{anyhow, bail, Result}; // Error handling
+use reqwest::Client; // Makes HTTP requests to Alchemy
+use serde_json::{json, Value}; // Parses JSON
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::time::{Duration, Instant}; // Sets a timeout
+
+use crate::address::Address;
+use crate::health::{self, Endpoint, Health};
+use crate::ratelimit::{self, TokenBucket};
+
+/// Requests per JSON-RPC batch unless configured otherwise.
+pub const DEFAULT_BATCH_SIZE: usize = 50;
+
+/// JSON-RPC error codes worth retrying: rate limits (429, -32005 "limit exceeded", Ankr's
+/// -32016) and internal errors of a struggling node (-32603).
+const RETRYABLE_CODES: [i64; 4] = [429, -32005, -32016, -32603];
+
+/// Why an RPC call failed.
+#[derive(Debug)]
+pub enum RpcError {
+    /// The request never got an HTTP response: connection refused, timeout, reset.
+    Transport(reqwest::Error),
+    /// A non-success HTTP status without a JSON-RPC body, e.g. 429 or 503. Carries the
+    /// provider's `Retry-After` when it sent one.
+    Http { status: u16, retry_after: Option<Duration> },
+    /// The node answered with a JSON-RPC `error` object.
+    JsonRpc { method: String, code: i64, message: String },
+    /// The body is not a JSON-RPC response or lacks the expected result.
+    Malformed { method: String, detail: String },
+    /// A `null` result for a block or its receipts: the endpoint has not reached `block` yet
+    /// (up to `block` for a batch), although another one reported it.
+    Missing { method: String, block: u64 },
+}
+
+impl RpcError {
+    /// The `RpcError` behind an error returned by this module, if it is one.
+    pub fn of(err: &anyhow::Error) -> Option<&RpcError> {
+        err.downcast_ref()
+    }
+
+    /// Whether repeating the same request may succeed: transport failures, 429 and 5xx
+    /// statuses, rate-limit or internal JSON-RPC errors, and blocks a node lacks yet. A query that returns too many
+    /// results fails the same way every time, so it is not retried.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            RpcError::Transport(_) => true,
+            RpcError::Http { status, .. } => *status == 429 || *status == 408 || *status >= 500,
+            RpcError::JsonRpc { code, .. } => RETRYABLE_CODES.contains(code) && !self.is_too_many_results(),
+            RpcError::Malformed { .. } => false,
+            RpcError::Missing { .. } => true,
+        }
+    }
+
+    /// Whether an `eth_getLogs` failure means the range must be narrowed. Providers word this
+    /// differently: Infura uses -32005 / "query returned more than 10000 results", Alchemy
+    /// "log response size exceeded", others report a block range limit.
+    pub fn is_too_many_results(&self) -> bool {
+        let RpcError::JsonRpc { message, .. } = self else {
+            return false;
+        };
+        let message = message.to_lowercase();
+        ["more than", "too many", "response size", "block range", "range is too large"]
+            .iter()
+            .any(|needle| message.contains(needle))
+    }
+
+    /// Whether the node does not offer the method (-32601, or a provider's wording of it).
+    pub fn is_method_not_found(&self) -> bool {
+        let RpcError::JsonRpc { code, message, .. } = self else {
+            return false;
+        };
+        let message = message.to_lowercase();
+        *code == -32601
+            || ["method not found", "does not exist", "not supported", "unsupported method"]
+                .iter()
+                .any(|needle| message.contains(needle))
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            RpcError::Http { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Transport(err) => write!(f, "transport error: {}", err),
+            RpcError::Http { status, .. } => write!(f, "HTTP status {}", status),
+            RpcError::JsonRpc { method, code, message } => write!(f, "{} failed: {} ({})", method, message, code),
+            RpcError::Malformed { method, detail } => write!(f, "{} returned a malformed response: {}", method, detail),
+            RpcError::Missing { method, block } => write!(f, "{} returned null: block {} not found", method, block),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+/// Exponential backoff for retryable failures: attempt `n` waits between half and all of
+/// `base * 2^n`, capped at `max`, so clients that failed together do not retry together.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base: Duration,
+    pub max: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { max_retries: 5, base: Duration::from_millis(250), max: Duration::from_secs(10) }
+    }
+}
+
+impl RetryPolicy {
+    fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self.base.saturating_mul(1 << attempt.min(16)).min(self.max);
+        // Random fraction in [0.5, 1) from the std hasher's per-process random keys.
+        let random = RandomState::new().hash_one(attempt) as f64 / u64::MAX as f64;
+        ceiling.mul_f64(0.5 + random / 2.0)
+    }
+}
+
+/// JSON-RPC client over one or more Polygon HTTP endpoints. Each request goes to the
+/// healthiest endpoint (see `health::pick`), and a failing or lagging one is avoided until
+/// it recovers.
+pub struct RpcClient {
+    client: Client,
+    endpoints: Vec<Endpoint>,
+    batch_size: usize,
+    retry: RetryPolicy,
+}
+
+impl RpcClient {
+    /// A client failing over between `urls`, preferring them in the order given while they
+    /// are equally healthy. Fails when `urls` is empty.
+    pub fn new(urls: Vec<String>) -> Result<Self> {
+        if urls.is_empty() {
+            bail!("at least one RPC endpoint is required");
+        }
+        Ok(Self {
+            client: Client::new(),
+            endpoints: urls.into_iter().map(Endpoint::new).collect(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            retry: RetryPolicy::default(),
+        })
+    }
+
+    /// Name and observed health of every endpoint, in configuration order.
+    pub fn health(&self) -> Vec<(&str, Health)> {
+        self.endpoints.iter().map(|e| (e.name(), e.health())).collect()
+    }
+
+    /// Caps how many calls `batch` packs into one HTTP request (at least 1).
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Paces requests to each endpoint's compute-unit budget (see `ratelimit::compute_units`).
+    /// Fails unless the budget is positive and finite.
+    pub fn with_rate_limit(mut self, compute_units_per_second: f64) -> Result<Self> {
+        for endpoint in &mut self.endpoints {
+            endpoint.limiter = Some(TokenBucket::new(compute_units_per_second)?);
+        }
+        Ok(self)
+    }
+
+    /// One HTTP round trip to endpoint `idx`, after waiting for `cost` compute units.
+    /// `label` names the call in errors.
+    async fn post(&self, idx: usize, body: &Value, label: &str, cost: f64) -> Result<Value, RpcError> {
+        let endpoint = &self.endpoints[idx];
+        if let Some(limiter) = &endpoint.limiter {
+            limiter.acquire(cost).await;
+        }
+        let res = self
+            .client
+            .post(&endpoint.url)
+            .json(body)
+            .timeout(Duration::from_secs(20))
+            .send()
+            .await
+            .map_err(RpcError::Transport)?;
+        let status = res.status();
+        let retry_after = res
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok()?.parse().ok())
+            .map(Duration::from_secs);
+        let bytes = res.bytes().await.map_err(RpcError::Transport)?;
+        match serde_json::from_slice::<Value>(&bytes) {
+            // Some nodes pair a JSON-RPC error with a 4xx/5xx status; the body says more.
+            Ok(body) if status.is_success() || body.get("error").is_some() => Ok(body),
+            _ if !status.is_success() => Err(RpcError::Http { status: status.as_u16(), retry_after }),
+            Ok(body) => Ok(body),
+            Err(err) => Err(RpcError::Malformed { method: label.to_string(), detail: err.to_string() }),
+        }
+    }
+
+    /// Awaits `attempt` against endpoint `idx` and records its latency and outcome.
+    async fn timed<T>(&self, idx: usize, attempt: impl Future<Output = Result<T, RpcError>>) -> Result<T, RpcError> {
+        let started = Instant::now();
+        let outcome = attempt.await;
+        self.endpoints[idx].record(started.elapsed(), &outcome);
+        outcome
+    }
+
+    /// Runs `attempt` on the healthiest endpoint that has reached `block` (when the request
+    /// is about one) until it succeeds, fails for good, or runs out of retries. A retry goes
+    /// straight to another endpoint when one is available and otherwise backs off first.
+    async fn retrying<T, F, Fut>(&self, label: &str, block: Option<u64>, mut attempt: F) -> Result<T>
+    where
+        F: FnMut(usize) -> Fut,
+        Fut: Future<Output = Result<T, RpcError>>,
+    {
+        let mut retries = 0;
+        loop {
+            let idx = health::pick(&self.endpoints, Instant::now(), block);
+            match self.timed(idx, attempt(idx)).await {
+                Ok(value) => return Ok(value),
+                Err(err) if err.is_retryable() && retries < self.retry.max_retries => {
+                    if !health::has_alternative(&self.endpoints, idx, Instant::now(), block) {
+                        let delay = err.retry_after().unwrap_or_else(|| self.retry.backoff(retries));
+                        println!("{} failed ({}), retrying in {:?}", label, err, delay);
+                        tokio::time::sleep(delay).await;
+                    }
+                    retries += 1;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Sends one JSON-RPC request and returns its `result`, failing on a JSON-RPC `error`.
+    /// Retryable failures are retried with backoff.
+    pub async fn call(&self, method: &str, params: Value) -> Result<Value> {
+        self.call_at(method, params, None).await
+    }
+
+    /// `call` for a request about `block`: only endpoints that have reached it are asked, and
+    /// a `null` result is a retryable `RpcError::Missing` rather than an answer.
+    async fn call_at(&self, method: &str, params: Value, block: Option<u64>) -> Result<Value> {
+        let body = request(method, params, 1);
+        let cost = ratelimit::compute_units(method);
+        self.retrying(method, block, |idx| {
+            let body = &body;
+            async move {
+                let res = self.post(idx, body, method, cost).await?;
+                missing_if_null(method, block, response_result(method, &res)?)
+            }
+        })
+        .await
+    }
+
+    /// Sends `calls` as JSON-RPC batches of up to `batch_size` requests each and returns
+    /// one result per call, in order. Responses are matched back by `id`, since a node may
+    /// answer a batch in any order; a failed call only fails its own slot and is not
+    /// retried. The outer `Result` fails when a whole batch is lost or rejected after
+    /// retries.
+    pub async fn batch(&self, calls: &[(&str, Value)]) -> Result<Vec<Result<Value>>> {
+        self.batch_at(calls, None).await
+    }
+
+    /// `batch` for requests about blocks up to `block`: only endpoints that have reached it
+    /// are asked, and a `null` result fails its slot with `RpcError::Missing`.
+    async fn batch_at(&self, calls: &[(&str, Value)], block: Option<u64>) -> Result<Vec<Result<Value>>> {
+        let mut results = Vec::with_capacity(calls.len());
+        for chunk in calls.chunks(self.batch_size) {
+            let body: Vec<Value> = chunk
+                .iter()
+                .enumerate()
+                .map(|(id, (method, params))| request(method, params.clone(), id as u64))
+                .collect();
+            let body = Value::Array(body);
+            let cost = chunk.iter().map(|(method, _)| ratelimit::compute_units(method)).sum();
+            let label = format!("batch of {} calls", chunk.len());
+            let res = self.retrying(&label, block, |idx| self.post(idx, &body, &label, cost)).await?;
+            let Value::Array(responses) = res else {
+                // Nodes that refuse batches answer with a single error object.
+                let err = response_result(&label, &res).err().unwrap_or_else(|| RpcError::Malformed {
+                    method: label.clone(),
+                    detail: "expected an array".to_string(),
+                });
+                return Err(err.into());
+            };
+            let mut by_id: HashMap<u64, Value> = responses
+                .into_iter()
+                .filter_map(|r| Some((r.get("id")?.as_u64()?, r)))
+                .collect();
+            for (id, (method, _)) in chunk.iter().enumerate() {
+                let result = match by_id.remove(&(id as u64)) {
+                    Some(res) => response_result(method, &res).and_then(|value| missing_if_null(method, block, value)),
+                    None => Err(RpcError::Malformed {
+                        method: method.to_string(),
+                        detail: "no response with its id in the batch".to_string(),
+                    }),
+                };
+                results.push(result.map_err(anyhow::Error::from));
+            }
+        }
+        Ok(results)
+    }
+
+    /// Latest block height via `eth_blockNumber`. With several endpoints every one is asked,
+    /// which keeps their head heights current for lag detection, and the highest answer
+    /// wins; if all fail, the usual retrying path takes over.
+    pub async fn block_number(&self) -> Result<u64> {
+        if self.endpoints.len() > 1 {
+            let answers = self.ask_all("eth_blockNumber", json!([])).await;
+            let mut best = None;
+            for (endpoint, answer) in self.endpoints.iter().zip(&answers) {
+                if let Ok(head) = answer.as_ref().map_err(|_| ()).and_then(|v| parse_quantity(v).map_err(|_| ())) {
+                    endpoint.record_head(head);
+                    best = best.max(Some(head));
+                }
+            }
+            if let Some(head) = best {
+                return Ok(head);
+            }
+        }
+        let res = self.call("eth_blockNumber", json!([])).await?;
+        parse_quantity(&res)
+    }
+
+    /// Sends the same request to every endpoint at once, without retries, and returns each
+    /// endpoint's answer in configuration order.
+    pub async fn ask_all(&self, method: &str, params: Value) -> Vec<Result<Value>> {
+        let body = request(method, params, 1);
+        let cost = ratelimit::compute_units(method);
+        futures::future::join_all((0..self.endpoints.len()).map(|idx| {
+            let body = &body;
+            async move {
+                let res = self
+                    .timed(idx, async move { response_result(method, &self.post(idx, body, method, cost).await?) })
+                    .await;
+                res.map_err(anyhow::Error::from)
+            }
+        }))
+        .await
+    }
+
+    /// Log-safe names of the endpoints, in the order `ask_all` answers.
+    pub fn endpoint_names(&self) -> Vec<&str> {
+        self.endpoints.iter().map(Endpoint::name).collect()
+    }
+
+    /// Header fields of a canonical block via `eth_getBlockByNumber` (without transactions).
+    pub async fn block_header(&self, number: u64) -> Result<BlockHeader> {
+        Ok(self.block(number, false).await?.0)
+    }
+
+    /// Height of the newest block the node considers final (the `finalized` block tag), or
+    /// `None` when it does not know one or predates the tag.
+    pub async fn finalized_block(&self) -> Result<Option<u64>> {
+        match self.call("eth_getBlockByNumber", json!(["finalized", false])).await {
+            Ok(Value::Null) => Ok(None),
+            Ok(block) => Ok(Some(parse_quantity(&block["number"])?)),
+            Err(err) if matches!(RpcError::of(&err), Some(RpcError::JsonRpc { .. })) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Headers of several canonical blocks, fetched in batches.
+    pub async fn block_headers(&self, numbers: &[u64]) -> Result<Vec<BlockHeader>> {
+        let calls: Vec<(&str, Value)> = numbers
+            .iter()
+            .map(|n| ("eth_getBlockByNumber", json!([format!("0x{:x}", n), false])))
+            .collect();
+        let mut headers = Vec::with_capacity(numbers.len());
+        for (number, res) in numbers.iter().zip(self.batch_at(&calls, numbers.iter().max().copied()).await?) {
+            headers.push(parse_block(*number, &res?)?.0);
+        }
+        Ok(headers)
+    }
+
+    /// A canonical block's header plus its transactions: full objects when
+    /// `full_transactions` is set, otherwise just their hashes.
+    pub async fn block(&self, number: u64, full_transactions: bool) -> Result<(BlockHeader, Vec<Value>)> {
+        let res = self
+            .call_at("eth_getBlockByNumber", json!([format!("0x{:x}", number), full_transactions]), Some(number))
+            .await?;
+        parse_block(number, &res)
+    }
+
+    /// Call trees of every transaction in the block with `block_hash`, via
+    /// `debug_traceBlockByHash` with geth's built-in `callTracer`.
+    pub async fn debug_trace_block(&self, block_hash: &str) -> Result<Vec<Value>> {
+        let res = self
+            .call("debug_traceBlockByHash", json!([block_hash, { "tracer": "callTracer" }]))
+            .await?;
+        match res {
+            Value::Array(traces) => Ok(traces),
+            other => bail!("debug_traceBlockByHash returned non-array result: {}", other),
+        }
+    }
+
+    /// Flat call traces of a canonical block via `trace_block` (Erigon / OpenEthereum style).
+    pub async fn trace_block(&self, number: u64) -> Result<Vec<Value>> {
+        let res = self.call("trace_block", json!([format!("0x{:x}", number)])).await?;
+        match res {
+            Value::Array(traces) => Ok(traces),
+            other => bail!("trace_block returned non-array result: {}", other),
+        }
+    }
+
+    /// Receipts of every transaction in `blocks` (block hashes or hex numbers, the newest of
+    /// them at height `newest`), one `eth_getBlockReceipts` per block, batched. Nodes
+    /// without that method are asked for the receipts of `tx_hashes` with batched
+    /// `eth_getTransactionReceipt` calls instead.
+    pub async fn block_receipts(&self, blocks: &[&str], tx_hashes: &[&str], newest: u64) -> Result<Vec<Value>> {
+        let calls: Vec<(&str, Value)> = blocks.iter().map(|b| ("eth_getBlockReceipts", json!([b]))).collect();
+        let mut receipts = Vec::new();
+        for res in self.batch_at(&calls, Some(newest)).await? {
+            match res {
+                Ok(Value::Array(block)) => receipts.extend(block),
+                Ok(other) => bail!("eth_getBlockReceipts returned non-array result: {}", other),
+                Err(err) if is_method_not_found(&err) => return self.transaction_receipts(tx_hashes, newest).await,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(receipts)
+    }
+
+    async fn transaction_receipts(&self, tx_hashes: &[&str], newest: u64) -> Result<Vec<Value>> {
+        let calls: Vec<(&str, Value)> = tx_hashes
+            .iter()
+            .map(|h| ("eth_getTransactionReceipt", json!([h])))
+            .collect();
+        self.batch_at(&calls, Some(newest)).await?.into_iter().collect()
+    }
+
+    /// Logs emitted by any of `addresses` with any of `topic0s` as first topic in exactly the
+    /// block `header`, selected by hash so a reorg between fetching the header and the logs
+    /// cannot mix two forks.
+    pub async fn get_logs_by_hash(&self, header: &BlockHeader, addresses: &[Address], topic0s: &[&str]) -> Result<Vec<Value>> {
+        let res = self
+            .call_at(
+                "eth_getLogs",
+                json!([{
+                    "blockHash": header.hash,
+                    "address": addresses,
+                    "topics": [topic0s]
+                }]),
+                Some(header.number),
+            )
+            .await?;
+        match res {
+            Value::Array(logs) => Ok(logs),
+            other => bail!("eth_getLogs returned non-array result: {}", other),
+        }
+    }
+
+    /// Logs emitted by any of `addresses` with any of `topic0s` as first topic in the
+    /// inclusive block range. Only endpoints that have reached `to_block` are asked, since a
+    /// lagging one would silently answer for less.
+    pub async fn get_logs(&self, from_block: u64, to_block: u64, addresses: &[Address], topic0s: &[&str]) -> Result<Vec<Value>> {
+        let res = self
+            .call_at(
+                "eth_getLogs",
+                json!([{
+                    "fromBlock": format!("0x{:x}", from_block),
+                    "toBlock": format!("0x{:x}", to_block),
+                    "address": addresses,
+                    "topics": [topic0s]
+                }]),
+                Some(to_block),
+            )
+            .await?;
+        match res {
+            Value::Array(logs) => Ok(logs),
+            other => bail!("eth_getLogs returned non-array result: {}", other),
+        }
+    }
+}
+
+/// The parts of a block header needed to detect reorgs and timestamp transfers.
+#[derive(Debug, Clone)]
+pub struct BlockHeader {
+    pub number: u64,
+    pub hash: String,
+    pub parent_hash: String,
+    /// Unix seconds.
+    pub timestamp: i64,
+}
+
+fn request(method: &str, params: Value, id: u64) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": method,
+        "params": params,
+        "id": id
+    })
+}
+
+/// The `result` of one JSON-RPC response, or its `error` as a failure.
+fn response_result(method: &str, res: &Value) -> Result<Value, RpcError> {
+    if let Some(err) = res.get("error") {
+        return Err(RpcError::JsonRpc {
+            method: method.to_string(),
+            code: err["code"].as_i64().unwrap_or_default(),
+            message: err["message"].as_str().map_or_else(|| err.to_string(), str::to_string),
+        });
+    }
+    res.get("result").cloned().ok_or_else(|| RpcError::Malformed {
+        method: method.to_string(),
+        detail: format!("no result in {}", res),
+    })
+}
+
+/// `value`, unless it is `null` for a request about `block`.
+fn missing_if_null(method: &str, block: Option<u64>, value: Value) -> Result<Value, RpcError> {
+    match block {
+        Some(block) if value.is_null() => Err(RpcError::Missing { method: method.to_string(), block }),
+        _ => Ok(value),
+    }
+}
+
+fn parse_block(number: u64, res: &Value) -> Result<(BlockHeader, Vec<Value>)> {
+    if res.is_null() {
+        return Err(RpcError::Missing { method: "eth_getBlockByNumber".to_string(), block: number }.into());
+    }
+    let header = BlockHeader {
+        number: parse_quantity(&res["number"])?,
+        hash: hex_string(res, "hash")?,
+        parent_hash: hex_string(res, "parentHash")?,
+        timestamp: parse_quantity(&res["timestamp"])? as i64,
+    };
+    let transactions = match &res["transactions"] {
+        Value::Array(txs) => txs.clone(),
+        _ => Vec::new(),
+    };
+    Ok((header, transactions))
+}
+
+fn hex_string(value: &Value, key: &str) -> Result<String> {
+    value[key]
+        .as_str()
+        .map(str::to_lowercase)
+        .ok_or_else(|| anyhow!("missing {} in {}", key, value))
+}
+
+/// Parses a `0x`-prefixed hex quantity such as a block number or log index.
+pub fn parse_quantity(value: &Value) -> Result<u64> {
+    let hex = value
+        .as_str()
+        .ok_or_else(|| anyhow!("expected hex string, got {}", value))?;
+    let digits = hex
+        .strip_prefix("0x")
+        .ok_or_else(|| anyhow!("missing 0x prefix: {}", hex))?;
+    Ok(u64::from_str_radix(digits, 16)?)
+}
+
+/// Whether the node does not offer the requested method.
+pub fn is_method_not_found(err: &anyhow::Error) -> bool {
+    RpcError::of(err).is_some_and(RpcError::is_method_not_found)
+}
+
+/// Whether an `eth_getLogs` failure means the range must be narrowed.
+pub fn is_too_many_results(err: &anyhow::Error) -> bool {
+    RpcError::of(err).is_some_and(RpcError::is_too_many_results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{FakeChain, FakeRpc};
+
+    fn fast_retries(max_retries: u32) -> RetryPolicy {
+        RetryPolicy { max_retries, base: Duration::from_millis(1), max: Duration::from_millis(5) }
+    }
+
+    #[tokio::test]
+    async fn batches_match_responses_by_id_and_keep_partial_errors() {
+        let fake = FakeRpc::start(FakeChain::linear(100, 104, 0)).await;
+        let rpc = RpcClient::new(vec![fake.url.clone()]).unwrap().with_batch_size(2);
+
+        let calls = [
+            ("eth_blockNumber", json!([])),
+            ("eth_getBlockByNumber", json!(["0x65", false])),
+            ("debug_unknownMethod", json!([])),
+            ("eth_getBlockByNumber", json!(["0x67", false])),
+            ("eth_blockNumber", json!([])),
+        ];
+        let results = rpc.batch(&calls).await.unwrap();
+        assert_eq!(results.len(), 5);
+        assert_eq!(parse_quantity(results[0].as_ref().unwrap()).unwrap(), 104);
+        assert_eq!(parse_quantity(&results[1].as_ref().unwrap()["number"]).unwrap(), 101);
+        assert!(is_method_not_found(results[2].as_ref().unwrap_err()));
+        assert_eq!(parse_quantity(&results[3].as_ref().unwrap()["number"]).unwrap(), 103);
+        assert_eq!(parse_quantity(results[4].as_ref().unwrap()).unwrap(), 104);
+
+        let headers = rpc.block_headers(&[104, 100, 102]).await.unwrap();
+        assert_eq!(headers.iter().map(|h| h.number).collect::<Vec<_>>(), vec![104, 100, 102]);
+    }
+
+    #[tokio::test]
+    async fn retries_rate_limits_and_reports_typed_errors() {
+        let fake = FakeRpc::start(FakeChain::linear(100, 104, 0)).await;
+        let rpc = RpcClient::new(vec![fake.url.clone()]).unwrap().with_retry_policy(fast_retries(3));
+
+        fake.fail_next(429, 3);
+        assert_eq!(rpc.block_number().await.unwrap(), 104);
+
+        fake.fail_next(503, 4);
+        let err = rpc.block_number().await.unwrap_err();
+        assert!(matches!(RpcError::of(&err), Some(RpcError::Http { status: 503, .. })));
+
+        // A JSON-RPC error that is not transient comes back typed, without retries.
+        let err = rpc.call("debug_unknownMethod", json!([])).await.unwrap_err();
+        assert!(matches!(RpcError::of(&err), Some(RpcError::JsonRpc { code: -32601, .. })));
+        assert!(is_method_not_found(&err));
+        assert!(!RpcError::of(&err).unwrap().is_retryable());
+    }
+
+    #[tokio::test]
+    async fn fails_over_from_erroring_and_lagging_endpoints() {
+        assert!(RpcClient::new(Vec::new()).is_err());
+        let primary = FakeRpc::start(FakeChain::linear(100, 120, 0)).await;
+        let backup = FakeRpc::start(FakeChain::linear(100, 120, 0)).await;
+        let rpc = RpcClient::new(vec![primary.url.clone(), backup.url.clone()]).unwrap().with_retry_policy(fast_retries(3));
+
+        // The primary, preferred while nothing is known, fails: the call moves to the
+        // backup, which keeps the traffic while the primary cools down.
+        primary.fail_next(503, 1);
+        rpc.block_header(101).await.unwrap();
+        rpc.block_header(102).await.unwrap();
+        assert_eq!((primary.requests(), backup.requests()), (1, 2));
+        let health = rpc.health();
+        assert!(health[0].1.error_rate > 0.0);
+        assert_eq!(health[1].1.error_rate, 0.0);
+
+        // Every endpoint reports its head; the primary answers again and the backup now
+        // lags behind, so traffic returns to the primary.
+        backup.set_chain(FakeChain::linear(100, 110, 0));
+        assert_eq!(rpc.block_number().await.unwrap(), 120);
+        assert_eq!(rpc.health()[1].1.head, Some(110));
+        rpc.block_header(103).await.unwrap();
+        assert_eq!((primary.requests(), backup.requests()), (3, 3));
+    }
+
+    #[tokio::test]
+    async fn sends_block_requests_only_to_endpoints_that_have_reached_the_block() {
+        let primary = FakeRpc::start(FakeChain::linear(100, 105, 0)).await;
+        let backup = FakeRpc::start(FakeChain::linear(100, 103, 0)).await;
+        let rpc = RpcClient::new(vec![primary.url.clone(), backup.url.clone()]).unwrap().with_retry_policy(fast_retries(3));
+
+        // The primary fails to report its head and cools down; the backup takes what it has...
+        primary.fail_next(503, 1);
+        assert_eq!(rpc.block_number().await.unwrap(), 103);
+        rpc.block_header(103).await.unwrap();
+        assert_eq!((primary.requests(), backup.requests()), (1, 2));
+        // ...but not a block past its head: that goes to the primary despite its cooldown.
+        assert_eq!(rpc.block_header(104).await.unwrap().number, 104);
+        assert_eq!(rpc.block_receipts(&["0x69"], &[], 105).await.unwrap(), Vec::<Value>::new());
+        assert_eq!((primary.requests(), backup.requests()), (3, 2));
+
+        // A node answering null for a block it lacks fails with a retryable error.
+        let lagging = RpcClient::new(vec![backup.url.clone()]).unwrap().with_retry_policy(fast_retries(1));
+        let err = lagging.block_header(104).await.unwrap_err();
+        assert!(matches!(RpcError::of(&err), Some(RpcError::Missing { block: 104, .. })), "{}", err);
+        assert!(RpcError::of(&err).unwrap().is_retryable());
+        let err = lagging.block_receipts(&["0x68"], &[], 104).await.unwrap_err();
+        assert!(matches!(RpcError::of(&err), Some(RpcError::Missing { .. })), "{}", err);
+    }
+
+    #[test]
+    fn classifies_log_range_errors_and_backs_off_exponentially() {
+        let too_many = RpcError::JsonRpc {
+            method: "eth_getLogs".into(),
+            code: -32005,
+            message: "query returned more than 10000 results".into(),
+        };
+        assert!(too_many.is_too_many_results() && !too_many.is_retryable());
+        let rate_limited = RpcError::JsonRpc {
+            method: "eth_getLogs".into(),
+            code: -32005,
+            message: "daily request count exceeded, request rate limited".into(),
+        };
+        assert!(!rate_limited.is_too_many_results() && rate_limited.is_retryable());
+
+        let policy = RetryPolicy::default();
+        for attempt in 0..8 {
+            let ceiling = (policy.base * 2u32.pow(attempt)).min(policy.max);
+            let delay = policy.backoff(attempt);
+            assert!(delay >= ceiling / 2 && delay <= ceiling, "attempt {}: {:?}", attempt, delay);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use anyhow::{anyhow, Result}; // Error handling
+use std::future::Future;
+
+/// A statement parameter or result column, in the handful of types the schema uses.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+}
+
+impl SqlValue {
+    pub fn as_str(&self) -> Result<&str> {
+        match self {
+            SqlValue::Text(text) => Ok(text),
+            other => Err(anyhow!("expected a text column, got {:?}", other)),
+        }
+    }
+
+    pub fn as_i64(&self) -> Result<i64> {
+        self.as_opt_i64()?.ok_or_else(|| anyhow!("expected an integer column, got NULL"))
+    }
+
+    /// An integer column that may be NULL.
+    pub fn as_opt_i64(&self) -> Result<Option<i64>> {
+        match self {
+            SqlValue::Null => Ok(None),
+            SqlValue::Integer(n) => Ok(Some(*n)),
+            other => Err(anyhow!("expected an integer column, got {:?}", other)),
+        }
+    }
+}
+
+impl From<i64> for SqlValue {
+    fn from(n: i64) -> Self {
+        SqlValue::Integer(n)
+    }
+}
+
+impl From<f64> for SqlValue {
+    fn from(x: f64) -> Self {
+        SqlValue::Real(x)
+    }
+}
+
+impl From<&str> for SqlValue {
+    fn from(text: &str) -> Self {
+        SqlValue::Text(text.to_string())
+    }
+}
+
+impl From<String> for SqlValue {
+    fn from(text: String) -> Self {
+        SqlValue::Text(text)
+    }
+}
+
+impl<T: Into<SqlValue>> From<Option<T>> for SqlValue {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(SqlValue::Null, Into::into)
+    }
+}
+
+/// Runs the statements shared by both stores (net-flow, its history and transfer inserts;
+/// see `netflow`). Statements use SQLite's numbered `?1` placeholders and SQL both
+/// dialects accept; each store binds and converts values its own way. Implemented by
+/// `rusqlite::Connection` and `postgres::PgStorage`'s transactions.
+pub trait Sql: Send {
+    /// Runs a statement and returns the number of rows it changed.
+    fn execute(&mut self, sql: &str, params: &[SqlValue]) -> impl Future<Output = Result<u64>> + Send;
+
+    /// Runs a query and returns every row's columns.
+    fn query(&mut self, sql: &str, params: &[SqlValue]) -> impl Future<Output = Result<Vec<Vec<SqlValue>>>> + Send;
+
+    /// The first row of a query, if any.
+    fn query_opt(&mut self, sql: &str, params: &[SqlValue]) -> impl Future<Output = Result<Option<Vec<SqlValue>>>> + Send {
+        async move { Ok(self.query(sql, params).await?.into_iter().next()) }
+    }
+}
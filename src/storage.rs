@@ -0,0 +1,329 @@
+use anyhow::Result; // Error handling
+use rusqlite::Connection; // Connects to SQLite database
+use std::future::Future;
+
+use crate::address::Address;
+use crate::amount::{self, NetAmount};
+use crate::config::Config;
+use crate::db;
+use crate::decode::{Receipt, Transfer};
+use crate::migrations::Migration;
+use crate::netflow::{self, FlowIndex};
+use crate::rpc::BlockHeader;
+
+/// Where the indexer keeps transfers, its block bookkeeping, checkpoints and net-flow
+/// figures. Each method is atomic: it applies fully or not at all. SQLite is implemented
+/// on `rusqlite::Connection` itself, PostgreSQL by `postgres::PgStorage`.
+pub trait Storage: Send {
+    /// Applies the schema migrations the store has not seen yet, in order, and returns them;
+    /// with `dry_run` only returns them. Fails on a store migrated by a newer build.
+    fn migrate(&mut self, dry_run: bool) -> impl Future<Output = Result<Vec<&'static Migration>>> + Send;
+
+    /// Version of the last migration applied, 0 on a fresh store.
+    fn schema_version(&mut self) -> impl Future<Output = Result<u32>> + Send;
+
+    /// The follower checkpoint, `None` on a fresh store.
+    fn last_indexed_block(&mut self) -> impl Future<Output = Result<Option<u64>>> + Send;
+
+    fn set_last_indexed_block(&mut self, block: u64) -> impl Future<Output = Result<()>> + Send;
+
+    /// Hash stored for an indexed block, `None` if that height was never indexed.
+    fn block_hash(&mut self, number: u64) -> impl Future<Output = Result<Option<String>>> + Send;
+
+    /// Stores a block's tracked transfers (each with its token's decimals), folding the new
+    /// and successful ones into net-flow, records its hash and advances the checkpoint to it.
+    fn commit_block(
+        &mut self,
+        config: &Config,
+        header: &BlockHeader,
+        transfers: &[(Transfer, u32)],
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Stores backfilled transfers with their token's decimals and block time, folding the
+    /// new and successful ones into net-flow. Blocks and the checkpoint are left alone.
+    fn store_transfers(
+        &mut self,
+        config: &Config,
+        transfers: &[(Transfer, u32, i64)],
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Takes every block and transfer above `ancestor` back out of net-flow, deletes them
+    /// and moves the checkpoint to `ancestor`. Returns the number of transfers deleted.
+    fn rollback_to(&mut self, config: &Config, ancestor: u64) -> impl Future<Output = Result<usize>> + Send;
+
+    /// Newest finalized block recorded by `update_finality`.
+    fn finalized_block(&mut self) -> impl Future<Output = Result<Option<u64>>> + Send;
+
+    /// Marks blocks at or below `confirmed` as confirmed and at or below `finalized` as
+    /// finalized, and refreshes the finalized net-flow figures.
+    fn update_finality(
+        &mut self,
+        config: &Config,
+        confirmed: u64,
+        finalized: Option<u64>,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Records a block that failed the `kind` quorum check.
+    fn quarantine_block(
+        &mut self,
+        number: u64,
+        block_hash: &str,
+        kind: &str,
+        answers: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Recomputes net-flow and its history from all transfers; returns a description of
+    /// each pair whose incrementally maintained value differed.
+    fn rebuild_net_flow(&mut self, config: &Config) -> impl Future<Output = Result<Vec<String>>> + Send;
+
+    /// What `rebuild_net_flow` would report, without changing anything.
+    fn check_net_flow(&mut self, config: &Config) -> impl Future<Output = Result<Vec<String>>> + Send;
+
+    /// Stored transfers in blocks `from..=to` with their block time (`None` for legacy rows),
+    /// ordered by block and log index.
+    fn transfers(&mut self, from: u64, to: u64) -> impl Future<Output = Result<Vec<(Transfer, Option<i64>)>>> + Send;
+
+    /// Every stored net-flow figure, ordered by exchange and token.
+    fn net_flows(&mut self) -> impl Future<Output = Result<Vec<NetFlowRow>>> + Send;
+}
+
+/// One exchange's net-flow of one token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetFlowRow {
+    pub exchange: String,
+    pub token_address: Address,
+    /// Over every indexed block, the tip included.
+    pub cumulative_raw: NetAmount,
+    /// Over finalized blocks only; `None` until a finalized block is known.
+    pub finalized_raw: Option<NetAmount>,
+}
+
+impl Storage for Connection {
+    async fn migrate(&mut self, dry_run: bool) -> Result<Vec<&'static Migration>> {
+        db::migrate(self, dry_run)
+    }
+
+    async fn schema_version(&mut self) -> Result<u32> {
+        db::schema_version(self)
+    }
+
+    async fn last_indexed_block(&mut self) -> Result<Option<u64>> {
+        db::last_indexed_block(self)
+    }
+
+    async fn set_last_indexed_block(&mut self, block: u64) -> Result<()> {
+        db::set_last_indexed_block(self, block)
+    }
+
+    async fn block_hash(&mut self, number: u64) -> Result<Option<String>> {
+        db::block_hash(self, number)
+    }
+
+    async fn commit_block(&mut self, config: &Config, header: &BlockHeader, transfers: &[(Transfer, u32)]) -> Result<()> {
+        let mut tx = db::Tx::begin(self)?;
+        let rows: Vec<_> = transfers.iter().map(|(t, d)| (t, *d, header.timestamp)).collect();
+        netflow::insert_transfers(&mut *tx, config, &rows).await?;
+        db::insert_block(&tx, header)?;
+        db::set_last_indexed_block(&tx, header.number)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn store_transfers(&mut self, config: &Config, transfers: &[(Transfer, u32, i64)]) -> Result<()> {
+        let mut tx = db::Tx::begin(self)?;
+        let rows: Vec<_> = transfers.iter().map(|(t, d, time)| (t, *d, *time)).collect();
+        netflow::insert_transfers(&mut *tx, config, &rows).await?;
+        tx.commit()
+    }
+
+    async fn rollback_to(&mut self, config: &Config, ancestor: u64) -> Result<usize> {
+        let mut tx = db::Tx::begin(self)?;
+        netflow::revert_above(&mut *tx, &FlowIndex::new(config), ancestor).await?;
+        let deleted = db::rollback_to(&tx, ancestor)?;
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    async fn finalized_block(&mut self) -> Result<Option<u64>> {
+        db::finalized_block(self)
+    }
+
+    async fn update_finality(&mut self, config: &Config, confirmed: u64, finalized: Option<u64>) -> Result<()> {
+        let mut tx = db::Tx::begin(self)?;
+        db::update_block_statuses(&tx, confirmed, finalized)?;
+        netflow::refresh_finalized(&mut *tx, config, finalized).await?;
+        tx.commit()
+    }
+
+    async fn quarantine_block(&mut self, number: u64, block_hash: &str, kind: &str, answers: &str) -> Result<()> {
+        db::quarantine_block(self, number, block_hash, kind, answers)
+    }
+
+    async fn rebuild_net_flow(&mut self, config: &Config) -> Result<Vec<String>> {
+        let mut tx = db::Tx::begin(self)?;
+        let mismatches = netflow::rebuild(&mut *tx, config).await?;
+        tx.commit()?;
+        Ok(mismatches)
+    }
+
+    async fn check_net_flow(&mut self, config: &Config) -> Result<Vec<String>> {
+        // Dropping the transaction uncommitted rolls the rebuild back.
+        let mut tx = db::Tx::begin(self)?;
+        netflow::rebuild(&mut *tx, config).await
+    }
+
+    async fn transfers(&mut self, from: u64, to: u64) -> Result<Vec<(Transfer, Option<i64>)>> {
+        let mut stmt = self.prepare(
+            "SELECT tx_hash, log_index, call_path, block_number, timestamp_unix, from_addr, to_addr, token_address,
+                    amount_raw, tx_fee_raw, receipt_status
+             FROM transfers WHERE block_number BETWEEN ?1 AND ?2 ORDER BY block_number, log_index",
+        )?;
+        let mut rows = stmt.query([from as i64, to as i64])?;
+        let mut transfers = Vec::new();
+        while let Some(row) = rows.next()? {
+            let transfer = Transfer {
+                tx_hash: row.get(0)?,
+                log_index: row.get(1)?,
+                call_path: row.get(2)?,
+                block_number: row.get::<_, i64>(3)? as u64,
+                from: row.get(5)?,
+                to: row.get(6)?,
+                token_address: row.get(7)?,
+                amount_raw: amount::parse_raw(&row.get::<_, String>(8)?)?,
+                receipt: receipt(row.get(9)?, row.get(10)?)?,
+            };
+            transfers.push((transfer, row.get(4)?));
+        }
+        Ok(transfers)
+    }
+
+    async fn net_flows(&mut self) -> Result<Vec<NetFlowRow>> {
+        let mut stmt = self.prepare(
+            "SELECT exchange, token_address, cumulative_amount_raw, finalized_amount_raw FROM net_flow
+             ORDER BY exchange, token_address",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get::<_, String>(2)?, row.get::<_, Option<String>>(3)?))
+        })?;
+        let mut flows = Vec::new();
+        for row in rows {
+            let (exchange, token_address, cumulative, finalized) = row?;
+            flows.push(NetFlowRow {
+                exchange,
+                token_address,
+                cumulative_raw: NetAmount::parse(&cumulative)?,
+                finalized_raw: finalized.as_deref().map(NetAmount::parse).transpose()?,
+            });
+        }
+        Ok(flows)
+    }
+}
+
+/// The receipt stored as `tx_fee_raw` and `receipt_status`, `None` when none was fetched.
+pub(crate) fn receipt(fee_raw: Option<String>, status: Option<i64>) -> Result<Option<Receipt>> {
+    let Some(status) = status else {
+        return Ok(None);
+    };
+    let fee_raw = fee_raw.as_deref().map(amount::parse_raw).transpose()?;
+    Ok(Some(Receipt { success: status != 0, fee_raw }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexer::Indexer;
+    use crate::postgres::PgStorage;
+    use crate::rpc::RpcClient;
+    use crate::testutil::{test_config, FakeChain, FakeRpc, BINANCE, OTHER, POL_TOKEN_ADDRESS};
+    use ethnum::I256;
+
+    const ONE_POL: u128 = 1_000_000_000_000_000_000;
+
+    /// Binance's POL figures as `(cumulative, finalized)` whole tokens.
+    async fn binance_pol<S: Storage>(store: &mut S) -> (I256, Option<I256>) {
+        let token: Address = POL_TOKEN_ADDRESS.parse().unwrap();
+        let row = store
+            .net_flows()
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|row| row.exchange == "Binance" && row.token_address == token)
+            .unwrap();
+        let pol = |net: NetAmount| net.0 / I256::from(ONE_POL);
+        (pol(row.cumulative_raw), row.finalized_raw.map(pol))
+    }
+
+    /// Indexes a chain with a failed transaction, a reorg and advancing finality into `store`
+    /// and checks the net-flow figures along the way.
+    async fn exercise<S: Storage>(store: S) {
+        let mut chain = FakeChain::linear(100, 105, 0);
+        chain.add_transfer(101, OTHER, BINANCE, 3 * ONE_POL, 1);
+        chain.add_transfer(102, OTHER, BINANCE, 5 * ONE_POL, 2);
+        chain.fail_tx(102, 2);
+        chain.add_transfer(104, BINANCE, OTHER, ONE_POL, 3);
+        chain.finalized = Some(102);
+        let fake = FakeRpc::start(chain.clone()).await;
+        let rpc = RpcClient::new(vec![fake.url.clone()]).unwrap();
+        let mut indexer = Indexer::new(test_config(), store, rpc).await.unwrap().with_confirmations(0);
+        indexer.store_mut().set_last_indexed_block(99).await.unwrap();
+
+        assert_eq!(indexer.catch_up().await.unwrap(), 6);
+        assert_eq!(binance_pol(indexer.store_mut()).await, (I256::from(2), Some(I256::from(3))));
+
+        // Blocks 104 and 105 are orphaned; the new branch brings 7 POL in at 105.
+        let mut fork = chain.fork(103, 106, 1);
+        fork.add_transfer(105, OTHER, BINANCE, 7 * ONE_POL, 4);
+        fake.set_chain(fork.clone());
+        indexer.catch_up().await.unwrap();
+        assert_eq!(indexer.store_mut().block_hash(106).await.unwrap(), Some(fork.blocks[&106].hash.clone()));
+        assert_eq!(binance_pol(indexer.store_mut()).await, (I256::from(10), Some(I256::from(3))));
+
+        fork.finalized = Some(106);
+        fake.set_chain(fork);
+        indexer.catch_up().await.unwrap();
+        assert_eq!(indexer.store_mut().finalized_block().await.unwrap(), Some(106));
+        assert_eq!(binance_pol(indexer.store_mut()).await, (I256::from(10), Some(I256::from(10))));
+
+        // Backfilling indexed blocks reads their transfers again but counts nothing twice.
+        assert_eq!(indexer.backfill(100, 106).await.unwrap(), 3);
+        assert!(indexer.rebuild_net_flow().await.unwrap().is_empty());
+        assert_eq!(binance_pol(indexer.store_mut()).await, (I256::from(10), Some(I256::from(10))));
+
+        let transfers = indexer.store_mut().transfers(100, 106).await.unwrap();
+        let stored: Vec<(u64, bool)> =
+            transfers.iter().map(|(t, _)| (t.block_number, t.receipt.unwrap().success)).collect();
+        assert_eq!(stored, [(101, true), (102, false), (105, true)]);
+        // Checking against a config that no longer tracks the wallet reports the figure but
+        // leaves it alone.
+        let mut untracked = test_config();
+        untracked.exchanges[0].wallets.clear();
+        assert_eq!(indexer.store_mut().check_net_flow(&untracked).await.unwrap().len(), 1);
+        assert_eq!(binance_pol(indexer.store_mut()).await, (I256::from(10), Some(I256::from(10))));
+    }
+
+    #[tokio::test]
+    async fn sqlite_storage() {
+        exercise(Connection::open_in_memory().unwrap()).await;
+    }
+
+    /// Runs against `POSTGRES_TEST_URL` (e.g. `postgres://postgres@localhost/postgres`) in a
+    /// scratch `indexer_test` schema; skipped when the variable is unset.
+    #[tokio::test]
+    async fn postgres_storage() {
+        let Ok(url) = std::env::var("POSTGRES_TEST_URL") else {
+            println!("POSTGRES_TEST_URL not set, skipping");
+            return;
+        };
+        let store = PgStorage::connect(&url).await.unwrap();
+        store
+            .client()
+            .batch_execute(
+                "DROP SCHEMA IF EXISTS indexer_test CASCADE;
+                 CREATE SCHEMA indexer_test;
+                 SET search_path TO indexer_test;",
+            )
+            .await
+            .unwrap();
+        exercise(store).await;
+    }
+}
@@ -0,0 +1,472 @@
+//! Scripted JSON-RPC servers for tests: an HTTP one serving a chain that a test can swap
+//! out mid-run to simulate a reorganization, and a WebSocket one pushing subscriptions.
+
+use rusqlite::Connection;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast; // Fans pushed notifications out to every WebSocket connection
+use futures::{SinkExt, StreamExt}; // Reads and writes WebSocket frames
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::config::{Config, Exchange, Token, Wallet};
+use crate::db;
+use crate::decode::{LOG_TRANSFER_TOPIC, MRC20_ADDRESS, NATIVE_TOKEN_ADDRESS, TRANSFER_TOPIC};
+
+pub const POL_TOKEN_ADDRESS: &str = "0x0000000000000000000000000000000000001010";
+
+pub const BINANCE: &str = "0xF977814e90dA44bFA03b6295A0616a897441aceC";
+/// Fake blocks are two seconds apart starting from this unix time.
+pub const GENESIS_TIME: u64 = 1_700_000_000;
+pub const OTHER: &str = "0x1111222233334444555566667777888899990000";
+/// Every fake receipt reports this gas usage and price.
+pub const FAKE_GAS_USED: u64 = 21_000;
+pub const FAKE_GAS_PRICE: u64 = 30_000_000_000;
+
+#[derive(Clone)]
+pub struct FakeBlock {
+    pub hash: String,
+    pub parent_hash: String,
+    pub logs: Vec<Value>,
+    pub transactions: Vec<Value>,
+    /// Hashes of transactions whose receipt reports failure.
+    pub failed: Vec<String>,
+}
+
+/// A canonical chain keyed by block number.
+#[derive(Clone, Default)]
+pub struct FakeChain {
+    pub blocks: BTreeMap<u64, FakeBlock>,
+    /// Block served for the `finalized` tag, if any.
+    pub finalized: Option<u64>,
+    /// Range `eth_getLogs` queries matching more logs than this fail like Infura's do.
+    pub max_logs: Option<usize>,
+}
+
+impl FakeChain {
+    /// Blocks `start..=end` whose hashes embed `fork` so different forks never collide.
+    pub fn linear(start: u64, end: u64, fork: u64) -> Self {
+        let mut chain = Self::default();
+        chain.extend(start, end, fork);
+        chain
+    }
+
+    /// Keeps blocks up to `at` and replaces everything above with a new branch up to `end`.
+    pub fn fork(&self, at: u64, end: u64, fork: u64) -> Self {
+        let mut chain = self.clone();
+        chain.blocks.retain(|&n, _| n <= at);
+        chain.extend(at + 1, end, fork);
+        chain
+    }
+
+    fn extend(&mut self, start: u64, end: u64, fork: u64) {
+        for n in start..=end {
+            let parent_hash = match self.blocks.get(&(n.wrapping_sub(1))) {
+                Some(parent) => parent.hash.clone(),
+                None => format!("0x{:064x}", n.wrapping_sub(1)),
+            };
+            let hash = format!("0x{:032x}{:032x}", fork + 1, n);
+            self.blocks.insert(n, FakeBlock { hash, parent_hash, logs: Vec::new(), transactions: Vec::new(), failed: Vec::new() });
+        }
+    }
+
+    /// Adds a POL Transfer log to block `n` with a unique tx hash derived from `tag`.
+    pub fn add_transfer(&mut self, n: u64, from: &str, to: &str, amount_raw: u128, tag: u64) {
+        let block = self.blocks.get_mut(&n).expect("block exists");
+        let topic = |addr: &str| format!("0x{:0>64}", addr.trim_start_matches("0x").to_lowercase());
+        block.logs.push(json!({
+            "address": POL_TOKEN_ADDRESS,
+            "topics": [TRANSFER_TOPIC, topic(from), topic(to)],
+            "data": format!("0x{:064x}", amount_raw),
+            "blockNumber": format!("0x{:x}", n),
+            "blockHash": block.hash,
+            "transactionHash": format!("0x{:064x}", tag),
+            "logIndex": format!("0x{:x}", block.logs.len()),
+        }));
+    }
+
+    /// Adds a transaction moving native POL as top-level value; with `log_transfer` the block
+    /// also carries the MRC20 `LogTransfer` that Bor emits for it.
+    pub fn add_native_transfer(&mut self, n: u64, from: &str, to: &str, amount_raw: u128, tag: u64, log_transfer: bool) {
+        let block = self.blocks.get_mut(&n).expect("block exists");
+        let tx_hash = format!("0x{:064x}", tag);
+        block.transactions.push(json!({
+            "hash": tx_hash,
+            "from": from,
+            "to": to,
+            "value": format!("0x{:x}", amount_raw),
+        }));
+        if log_transfer {
+            let topic = |addr: &str| format!("0x{:0>64}", addr.trim_start_matches("0x").to_lowercase());
+            let word = |v: u128| format!("{:064x}", v);
+            block.logs.push(json!({
+                "address": MRC20_ADDRESS,
+                "topics": [LOG_TRANSFER_TOPIC, topic(&MRC20_ADDRESS.to_lower_hex()), topic(from), topic(to)],
+                "data": format!("0x{}{}{}{}{}", word(amount_raw), word(0), word(0), word(0), word(0)),
+                "blockNumber": format!("0x{:x}", n),
+                "blockHash": block.hash,
+                "transactionHash": tx_hash,
+                "logIndex": format!("0x{:x}", block.logs.len()),
+            }));
+        }
+    }
+
+    /// Marks the transaction tagged `tag` in block `n` as failed in its receipt.
+    pub fn fail_tx(&mut self, n: u64, tag: u64) {
+        let block = self.blocks.get_mut(&n).expect("block exists");
+        block.failed.push(format!("0x{:064x}", tag));
+    }
+
+    fn head(&self) -> u64 {
+        self.blocks.keys().next_back().copied().unwrap_or(0)
+    }
+
+    fn handle(&self, method: &str, params: &Value) -> Value {
+        match method {
+            "eth_blockNumber" => json!(format!("0x{:x}", self.head())),
+            "eth_getBlockByNumber" => {
+                let n = match params[0].as_str().unwrap() {
+                    "finalized" => match self.finalized {
+                        Some(n) => n,
+                        None => return Value::Null,
+                    },
+                    hex => u64::from_str_radix(hex.trim_start_matches("0x"), 16).unwrap(),
+                };
+                match self.blocks.get(&n) {
+                    Some(b) => json!({
+                        "number": format!("0x{:x}", n),
+                        "hash": b.hash,
+                        "parentHash": b.parent_hash,
+                        "timestamp": format!("0x{:x}", GENESIS_TIME + 2 * n),
+                        "transactions": if params[1].as_bool() == Some(true) { json!(b.transactions) } else { json!([]) },
+                    }),
+                    None => Value::Null,
+                }
+            }
+            "eth_getLogs" => json!(self.logs(&params[0])),
+            "eth_getBlockReceipts" => {
+                let id = params[0].as_str().unwrap();
+                let Some(b) = self
+                    .blocks
+                    .iter()
+                    .find(|(n, b)| b.hash == id || format!("0x{:x}", n) == id)
+                    .map(|(_, b)| b)
+                else {
+                    return Value::Null;
+                };
+                let mut hashes: Vec<&str> = b
+                    .transactions
+                    .iter()
+                    .chain(&b.logs)
+                    .map(|v| v["hash"].as_str().or_else(|| v["transactionHash"].as_str()).unwrap())
+                    .collect();
+                hashes.sort_unstable();
+                hashes.dedup();
+                let receipts: Vec<Value> = hashes
+                    .into_iter()
+                    .map(|hash| {
+                        json!({
+                            "transactionHash": hash,
+                            "status": if b.failed.iter().any(|f| f == hash) { "0x0" } else { "0x1" },
+                            "gasUsed": format!("0x{:x}", FAKE_GAS_USED),
+                            "effectiveGasPrice": format!("0x{:x}", FAKE_GAS_PRICE),
+                        })
+                    })
+                    .collect();
+                json!(receipts)
+            }
+            _ => Value::Null,
+        }
+    }
+
+    /// Logs matching an `eth_getLogs` filter: one block by `blockHash` or an inclusive
+    /// `fromBlock`/`toBlock` range, narrowed to the filter's addresses and first topics.
+    fn logs(&self, filter: &Value) -> Vec<&Value> {
+        let quantity = |v: &Value| u64::from_str_radix(v.as_str().unwrap().trim_start_matches("0x"), 16).unwrap();
+        let matches = |list: &Value, value: &Value| {
+            list.as_array()
+                .is_none_or(|list| list.iter().any(|item| item.as_str().unwrap().eq_ignore_ascii_case(value.as_str().unwrap())))
+        };
+        self.blocks
+            .iter()
+            .filter(|&(&n, b)| match filter["blockHash"].as_str() {
+                Some(hash) => b.hash == hash,
+                None => quantity(&filter["fromBlock"]) <= n && n <= quantity(&filter["toBlock"]),
+            })
+            .flat_map(|(_, b)| b.logs.iter())
+            .filter(|log| matches(&filter["address"], &log["address"]) && matches(&filter["topics"][0], &log["topics"][0]))
+            .collect()
+    }
+
+    /// JSON-RPC response to one request; unknown methods get a -32601 error.
+    fn respond(&self, request: &Value) -> Value {
+        let method = request["method"].as_str().unwrap();
+        let error = |code: i64, message: String| {
+            json!({ "jsonrpc": "2.0", "id": request["id"], "error": { "code": code, "message": message } })
+        };
+        if !SUPPORTED.contains(&method) {
+            return error(-32601, format!("the method {} does not exist/is not available", method));
+        }
+        let filter = &request["params"][0];
+        if method == "eth_getLogs"
+            && filter["blockHash"].is_null()
+            && let Some(max) = self.max_logs
+            && self.logs(filter).len() > max
+        {
+            return error(-32005, format!("query returned more than {} results", max));
+        }
+        json!({ "jsonrpc": "2.0", "id": request["id"], "result": self.handle(method, &request["params"]) })
+    }
+}
+
+const SUPPORTED: [&str; 4] = ["eth_blockNumber", "eth_getBlockByNumber", "eth_getLogs", "eth_getBlockReceipts"];
+
+/// HTTP JSON-RPC server on an ephemeral localhost port; single requests and batches.
+pub struct FakeRpc {
+    pub url: String,
+    chain: Arc<Mutex<FakeChain>>,
+    failures: Arc<Mutex<Vec<u16>>>,
+    requests: Arc<AtomicUsize>,
+    log_ranges: Arc<Mutex<Vec<(u64, u64)>>>,
+}
+
+impl FakeRpc {
+    pub async fn start(chain: FakeChain) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let chain = Arc::new(Mutex::new(chain));
+        let failures = Arc::new(Mutex::new(Vec::new()));
+        let requests = Arc::new(AtomicUsize::new(0));
+        let log_ranges = Arc::new(Mutex::new(Vec::new()));
+        let state = (chain.clone(), failures.clone(), requests.clone(), log_ranges.clone());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, state.0.clone(), state.1.clone(), state.2.clone(), state.3.clone()));
+            }
+        });
+        Self { url, chain, failures, requests, log_ranges }
+    }
+
+    /// HTTP requests received so far, failed ones included.
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+
+    /// Block ranges of the range `eth_getLogs` queries received so far, rejected ones included.
+    pub fn log_ranges(&self) -> Vec<(u64, u64)> {
+        self.log_ranges.lock().unwrap().clone()
+    }
+
+    /// Answers the next `count` HTTP requests with `status` and an empty body.
+    pub fn fail_next(&self, status: u16, count: usize) {
+        self.failures.lock().unwrap().extend(std::iter::repeat_n(status, count));
+    }
+
+    pub fn set_chain(&self, chain: FakeChain) {
+        *self.chain.lock().unwrap() = chain;
+    }
+}
+
+async fn serve(
+    stream: TcpStream,
+    chain: Arc<Mutex<FakeChain>>,
+    failures: Arc<Mutex<Vec<u16>>>,
+    requests: Arc<AtomicUsize>,
+    log_ranges: Arc<Mutex<Vec<(u64, u64)>>>,
+) {
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await.unwrap_or(0) == 0 {
+                return;
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':')
+                && name.eq_ignore_ascii_case("content-length")
+            {
+                content_length = value.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).await.unwrap();
+        let request: Value = serde_json::from_slice(&body).unwrap();
+
+        requests.fetch_add(1, Ordering::SeqCst);
+        let filter = &request["params"][0];
+        if request["method"] == "eth_getLogs"
+            && let (Some(from), Some(to)) = (filter["fromBlock"].as_str(), filter["toBlock"].as_str())
+        {
+            let quantity = |hex: &str| u64::from_str_radix(hex.trim_start_matches("0x"), 16).unwrap();
+            log_ranges.lock().unwrap().push((quantity(from), quantity(to)));
+        }
+        let failure = failures.lock().unwrap().pop();
+        if let Some(status) = failure {
+            let http = format!("HTTP/1.1 {} Fake Failure\r\nRetry-After: 0\r\nContent-Length: 0\r\n\r\n", status);
+            if reader.get_mut().write_all(http.as_bytes()).await.is_err() {
+                return;
+            }
+            continue;
+        }
+        let response = match &request {
+            // Answer batches in reverse so clients must match responses by id.
+            Value::Array(batch) => {
+                let chain = chain.lock().unwrap();
+                json!(batch.iter().rev().map(|r| chain.respond(r)).collect::<Vec<_>>()).to_string()
+            }
+            single => chain.lock().unwrap().respond(single).to_string(),
+        };
+        let http = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            response.len(),
+            response
+        );
+        if reader.get_mut().write_all(http.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// What a test asks the fake WebSocket server to do on every open connection.
+#[derive(Clone)]
+enum WsCommand {
+    /// Notify subscribers of this kind (`"newHeads"` or `"logs"`).
+    Push(&'static str, Value),
+    /// Send this message as is.
+    Raw(Value),
+    Close,
+}
+
+/// WebSocket JSON-RPC server on an ephemeral localhost port answering `eth_subscribe`.
+pub struct FakeWs {
+    pub url: String,
+    commands: broadcast::Sender<WsCommand>,
+    connections: Arc<AtomicUsize>,
+    subscriptions: Arc<Mutex<Vec<String>>>,
+}
+
+impl FakeWs {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (commands, _) = broadcast::channel(64);
+        let connections = Arc::new(AtomicUsize::new(0));
+        let subscriptions = Arc::new(Mutex::new(Vec::new()));
+        let state = (commands.clone(), connections.clone(), subscriptions.clone());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                state.1.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(serve_ws(stream, state.0.subscribe(), state.2.clone()));
+            }
+        });
+        Self { url, commands, connections, subscriptions }
+    }
+
+    /// Connections accepted so far.
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// Subscription kinds requested on the latest connection, in order.
+    pub fn subscriptions(&self) -> Vec<String> {
+        self.subscriptions.lock().unwrap().clone()
+    }
+
+    pub fn push_head(&self, n: u64) {
+        self.push("newHeads", json!({ "number": format!("0x{:x}", n), "hash": format!("0x{:064x}", n) }));
+    }
+
+    /// A tracked log in block `n`; `removed` marks one retracted by a reorg.
+    pub fn push_log(&self, n: u64, removed: bool) {
+        self.push("logs", json!({ "blockNumber": format!("0x{:x}", n), "removed": removed }));
+    }
+
+    /// Sends `message` verbatim on every open connection.
+    pub fn push_raw(&self, message: Value) {
+        let _ = self.commands.send(WsCommand::Raw(message));
+    }
+
+    /// Closes every open connection, as a provider dropping the socket would.
+    pub fn drop_connections(&self) {
+        let _ = self.commands.send(WsCommand::Close);
+    }
+
+    fn push(&self, kind: &'static str, result: Value) {
+        let _ = self.commands.send(WsCommand::Push(kind, result));
+    }
+}
+
+async fn serve_ws(stream: TcpStream, mut commands: broadcast::Receiver<WsCommand>, subscriptions: Arc<Mutex<Vec<String>>>) {
+    let Ok(mut socket) = tokio_tungstenite::accept_async(stream).await else {
+        return;
+    };
+    subscriptions.lock().unwrap().clear();
+    // Subscription ids are "0x" + the kind, so pushes can address them directly.
+    loop {
+        tokio::select! {
+            message = socket.next() => {
+                let Some(Ok(Message::Text(text))) = message else {
+                    return;
+                };
+                let request: Value = serde_json::from_str(&text).unwrap();
+                let kind = request["params"][0].as_str().unwrap().to_string();
+                let response = json!({ "jsonrpc": "2.0", "id": request["id"], "result": format!("0x{}", kind) });
+                subscriptions.lock().unwrap().push(kind);
+                if socket.send(Message::text(response.to_string())).await.is_err() {
+                    return;
+                }
+            }
+            command = commands.recv() => match command {
+                Ok(WsCommand::Push(kind, result)) => {
+                    let notification = json!({
+                        "jsonrpc": "2.0",
+                        "method": "eth_subscription",
+                        "params": { "subscription": format!("0x{}", kind), "result": result },
+                    });
+                    if socket.send(Message::text(notification.to_string())).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(WsCommand::Raw(message)) => {
+                    if socket.send(Message::text(message.to_string())).await.is_err() {
+                        return;
+                    }
+                }
+                _ => {
+                    let _ = socket.close(None).await;
+                    return;
+                }
+            },
+        }
+    }
+}
+
+/// Fresh in-memory database with the full schema.
+pub fn memory_db() -> Connection {
+    let mut conn = Connection::open_in_memory().unwrap();
+    db::migrate(&mut conn, false).unwrap();
+    conn
+}
+
+/// Binance with the single `BINANCE` wallet, tracking POL as an ERC-20 and natively.
+pub fn test_config() -> Config {
+    Config {
+        exchanges: vec![Exchange {
+            name: "Binance".into(),
+            wallets: vec![Wallet { address: BINANCE.parse().unwrap(), label: None, kind: None }],
+        }],
+        tokens: vec![
+            Token { symbol: "POL".into(), address: POL_TOKEN_ADDRESS.parse().unwrap(), decimals: 18 },
+            Token { symbol: "POL (native)".into(), address: NATIVE_TOKEN_ADDRESS, decimals: 18 },
+        ],
+        trace: None,
+        quorum: None,
+    }
+}
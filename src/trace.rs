@@ -0,0 +1,238 @@
+use anyhow::{anyhow, bail, Result}; // Error handling
+use ethnum::U256; // Exact 256-bit token amounts
+use serde_json::Value; // Parses JSON
+use std::collections::HashMap;
+
+use crate::address::Address;
+use crate::amount;
+use crate::decode::{Transfer, NATIVE_TOKEN_ADDRESS};
+
+/// `log_index` of the call frame at `ordinal` in a transaction's call tree (depth-first,
+/// the top-level call is 0). The top-level call maps to `TX_VALUE_LOG_INDEX`, so a
+/// transaction's value is keyed the same whether it came from the block or a trace.
+pub fn call_log_index(ordinal: usize) -> i64 {
+    -1 - ordinal as i64
+}
+
+/// Native transfers in the output of `debug_traceBlockByHash` with the `callTracer`:
+/// one call tree per transaction, in block order. Older clients omit `txHash`, in which
+/// case `tx_hashes` (the block's transaction hashes) supply it by position.
+pub fn decode_call_tracer(block_number: u64, traces: &[Value], tx_hashes: &[String]) -> Result<Vec<Transfer>> {
+    let mut transfers = Vec::new();
+    for (position, trace) in traces.iter().enumerate() {
+        let tx_hash = match trace["txHash"].as_str() {
+            Some(hash) => hash.to_lowercase(),
+            None => tx_hashes
+                .get(position)
+                .cloned()
+                .ok_or_else(|| anyhow!("trace {} of block {} has no transaction", position, block_number))?,
+        };
+        if let Some(err) = trace.get("error") {
+            bail!("tracing {} failed: {}", tx_hash, err);
+        }
+        let mut ordinal = 0;
+        walk_call(&trace["result"], &tx_hash, block_number, "0", &mut ordinal, &mut transfers)?;
+    }
+    Ok(transfers)
+}
+
+/// Visits `frame` and its sub-calls depth-first. Reverted frames move nothing, so they
+/// and their sub-calls are skipped, though they still take an ordinal.
+fn walk_call(
+    frame: &Value,
+    tx_hash: &str,
+    block_number: u64,
+    path: &str,
+    ordinal: &mut usize,
+    out: &mut Vec<Transfer>,
+) -> Result<()> {
+    let index = *ordinal;
+    *ordinal += 1;
+    let calls = frame["calls"].as_array().map(Vec::as_slice).unwrap_or_default();
+    if frame.get("error").is_some() {
+        *ordinal += count_calls(calls);
+        return Ok(());
+    }
+    let kind = frame["type"].as_str().unwrap_or_default().to_uppercase();
+    // DELEGATECALL and STATICCALL never move value; the former echoes its parent's.
+    if matches!(kind.as_str(), "CALL" | "CREATE" | "CREATE2" | "SELFDESTRUCT") {
+        let value = match frame.get("value") {
+            Some(v) if !v.is_null() => amount::parse_quantity_u256(v)?,
+            _ => U256::ZERO,
+        };
+        if value != U256::ZERO {
+            out.push(native_transfer(
+                tx_hash,
+                index,
+                path,
+                block_number,
+                address(frame, "from")?,
+                address(frame, "to")?,
+                value,
+            ));
+        }
+    }
+    for (i, call) in calls.iter().enumerate() {
+        walk_call(call, tx_hash, block_number, &format!("{}.{}", path, i), ordinal, out)?;
+    }
+    Ok(())
+}
+
+fn count_calls(calls: &[Value]) -> usize {
+    calls
+        .iter()
+        .map(|c| 1 + count_calls(c["calls"].as_array().map(Vec::as_slice).unwrap_or_default()))
+        .sum()
+}
+
+/// Native transfers in the output of `trace_block`: a flat list of traces, each with a
+/// `traceAddress` locating it in its transaction's call tree, in depth-first order.
+pub fn decode_trace_block(block_number: u64, traces: &[Value]) -> Result<Vec<Transfer>> {
+    let mut transfers = Vec::new();
+    let mut ordinals: HashMap<String, usize> = HashMap::new();
+    let mut reverted: Vec<(String, Vec<u64>)> = Vec::new();
+    for trace in traces {
+        // Block and uncle rewards carry no transaction.
+        let Some(tx_hash) = trace["transactionHash"].as_str().map(str::to_lowercase) else {
+            continue;
+        };
+        let trace_address: Vec<u64> = trace["traceAddress"]
+            .as_array()
+            .ok_or_else(|| anyhow!("trace without traceAddress: {}", trace))?
+            .iter()
+            .map(|i| i.as_u64().ok_or_else(|| anyhow!("bad traceAddress in {}", trace)))
+            .collect::<Result<_>>()?;
+        let ordinal = ordinals.entry(tx_hash.clone()).or_insert(0);
+        let index = *ordinal;
+        *ordinal += 1;
+
+        if reverted
+            .iter()
+            .any(|(hash, prefix)| *hash == tx_hash && trace_address.starts_with(prefix))
+        {
+            continue;
+        }
+        if trace.get("error").is_some_and(|e| !e.is_null()) {
+            reverted.push((tx_hash, trace_address));
+            continue;
+        }
+
+        let action = &trace["action"];
+        let (from, to, value) = match trace["type"].as_str() {
+            Some("call") => {
+                if matches!(action["callType"].as_str(), Some("delegatecall" | "staticcall")) {
+                    continue;
+                }
+                (address(action, "from")?, address(action, "to")?, &action["value"])
+            }
+            Some("create") => (address(action, "from")?, address(&trace["result"], "address")?, &action["value"]),
+            Some("suicide") => (address(action, "address")?, address(action, "refundAddress")?, &action["balance"]),
+            _ => continue,
+        };
+        let value = amount::parse_quantity_u256(value)?;
+        if value == U256::ZERO {
+            continue;
+        }
+        let path = std::iter::once("0".to_string())
+            .chain(trace_address.iter().map(u64::to_string))
+            .collect::<Vec<_>>()
+            .join(".");
+        transfers.push(native_transfer(&tx_hash, index, &path, block_number, from, to, value));
+    }
+    Ok(transfers)
+}
+
+fn native_transfer(
+    tx_hash: &str,
+    ordinal: usize,
+    path: &str,
+    block_number: u64,
+    from: Address,
+    to: Address,
+    amount_raw: U256,
+) -> Transfer {
+    Transfer {
+        tx_hash: tx_hash.to_string(),
+        log_index: call_log_index(ordinal),
+        call_path: Some(path.to_string()),
+        block_number,
+        token_address: NATIVE_TOKEN_ADDRESS,
+        from,
+        to,
+        amount_raw,
+        receipt: None,
+    }
+}
+
+fn address(value: &Value, key: &str) -> Result<Address> {
+    value[key]
+        .as_str()
+        .ok_or_else(|| anyhow!("trace without {}: {}", key, value))?
+        .parse()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const A: &str = "0x00000000000000000000000000000000000000aa";
+    const B: &str = "0x00000000000000000000000000000000000000bb";
+    const C: &str = "0x00000000000000000000000000000000000000cc";
+
+    fn summary(transfers: &[Transfer]) -> Vec<(i64, &str, String, String, String)> {
+        transfers
+            .iter()
+            .map(|t| {
+                let path = t.call_path.as_deref().unwrap();
+                (t.log_index, path, t.from.to_lower_hex(), t.to.to_lower_hex(), t.amount_raw.to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn call_tracer_and_trace_block_agree_on_internal_transfers() {
+        // A calls router B with 10 wei; B pays 4 to C, delegatecalls itself, makes a reverted
+        // call whose sub-call is discarded, then pays 6 back to A.
+        let call_tree = json!([{
+            "txHash": "0x01",
+            "result": {
+                "type": "CALL", "from": A, "to": B, "value": "0xa",
+                "calls": [
+                    { "type": "CALL", "from": B, "to": C, "value": "0x4" },
+                    { "type": "DELEGATECALL", "from": B, "to": B, "value": "0xa" },
+                    {
+                        "type": "CALL", "from": B, "to": C, "value": "0x1", "error": "execution reverted",
+                        "calls": [{ "type": "CALL", "from": C, "to": A, "value": "0x1" }]
+                    },
+                    { "type": "CALL", "from": B, "to": A, "value": "0x6" }
+                ]
+            }
+        }]);
+        let flat = json!([
+            { "type": "call", "transactionHash": "0x01", "traceAddress": [],
+              "action": { "callType": "call", "from": A, "to": B, "value": "0xa" } },
+            { "type": "call", "transactionHash": "0x01", "traceAddress": [0],
+              "action": { "callType": "call", "from": B, "to": C, "value": "0x4" } },
+            { "type": "call", "transactionHash": "0x01", "traceAddress": [1],
+              "action": { "callType": "delegatecall", "from": B, "to": B, "value": "0xa" } },
+            { "type": "call", "transactionHash": "0x01", "traceAddress": [2], "error": "Reverted",
+              "action": { "callType": "call", "from": B, "to": C, "value": "0x1" } },
+            { "type": "call", "transactionHash": "0x01", "traceAddress": [2, 0],
+              "action": { "callType": "call", "from": C, "to": A, "value": "0x1" } },
+            { "type": "call", "transactionHash": "0x01", "traceAddress": [3],
+              "action": { "callType": "call", "from": B, "to": A, "value": "0x6" } },
+            { "type": "reward", "action": { "author": A, "value": "0x1" } }
+        ]);
+
+        let expected = vec![
+            (-1, "0", A.to_string(), B.to_string(), "10".to_string()),
+            (-2, "0.0", B.to_string(), C.to_string(), "4".to_string()),
+            (-6, "0.3", B.to_string(), A.to_string(), "6".to_string()),
+        ];
+        let traced = decode_call_tracer(7, call_tree.as_array().unwrap(), &[]).unwrap();
+        assert_eq!(summary(&traced), expected);
+        let flat = decode_trace_block(7, flat.as_array().unwrap()).unwrap();
+        assert_eq!(summary(&flat), expected);
+    }
+}
@@ -0,0 +1,191 @@
+use anyhow::{anyhow, bail, Result}; // Error handling
+use futures::{SinkExt, StreamExt}; // Reads and writes WebSocket frames
+use serde_json::{json, Value}; // Parses JSON
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::mpsc; // Hands events to the follower
+use tokio_tungstenite::tungstenite::Message; // WebSocket frames
+
+use crate::address::Address;
+use crate::health::host;
+use crate::rpc::parse_quantity;
+
+/// Reconnection delay after the first drop; doubles per failed attempt up to `MAX_RECONNECT_DELAY`.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+/// A socket silent for this long is assumed dead; Polygon produces a block every ~2 seconds.
+const STALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// What a subscription tells the follower.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// Subscribed, either for the first time or after the socket dropped. Heads announced
+    /// while disconnected were missed and must be filled in over HTTP.
+    Connected,
+    /// `newHeads` announced this block.
+    Head(u64),
+    /// A tracked log arrived in this block.
+    Log(u64),
+}
+
+/// `eth_subscribe` client for `newHeads` and, optionally, tracked logs. Runs in the
+/// background and reconnects on its own.
+pub struct Subscriber {
+    url: String,
+    log_filter: Option<Value>,
+    reconnect_delay: Duration,
+}
+
+impl Subscriber {
+    pub fn new(url: String) -> Self {
+        Self { url, log_filter: None, reconnect_delay: RECONNECT_DELAY }
+    }
+
+    /// Also subscribes to logs of `addresses` with any of `topic0s` as first topic.
+    pub fn with_logs(mut self, addresses: &[Address], topic0s: &[&str]) -> Self {
+        self.log_filter = Some(json!({ "address": addresses, "topics": [topic0s] }));
+        self
+    }
+
+    /// Delay before the first reconnection attempt.
+    pub fn with_reconnect_delay(mut self, delay: Duration) -> Self {
+        self.reconnect_delay = delay;
+        self
+    }
+
+    /// Connects in a background task and returns its events. The task reconnects with
+    /// exponential backoff whenever the socket fails or stalls, and ends once the receiver
+    /// is dropped.
+    pub fn spawn(self) -> mpsc::Receiver<Event> {
+        let (tx, rx) = mpsc::channel(256);
+        tokio::spawn(async move {
+            let mut delay = self.reconnect_delay;
+            loop {
+                match self.session(&tx).await {
+                    Ok(true) => delay = self.reconnect_delay,
+                    Ok(false) => {}
+                    Err(err) => println!("WebSocket {} failed: {:#}", host(&self.url), err),
+                }
+                if tx.is_closed() {
+                    return;
+                }
+                println!("WebSocket reconnecting in {:?}", delay);
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+            }
+        });
+        rx
+    }
+
+    /// One connection: subscribes, then forwards notifications until the socket closes or
+    /// the receiver is dropped. Returns whether the subscriptions were established, in which
+    /// case the next reconnection is attempted without backing off further.
+    async fn session(&self, tx: &mpsc::Sender<Event>) -> Result<bool> {
+        let (mut socket, _) = tokio_tungstenite::connect_async(self.url.as_str()).await?;
+        let mut requests = vec![json!(["newHeads"])];
+        if let Some(filter) = &self.log_filter {
+            requests.push(json!(["logs", filter]));
+        }
+        for (id, params) in requests.iter().enumerate() {
+            let request = json!({ "jsonrpc": "2.0", "id": id, "method": "eth_subscribe", "params": params });
+            socket.send(Message::text(request.to_string())).await?;
+        }
+
+        // Subscription id -> whether it carries logs (rather than heads).
+        let mut subscriptions: HashMap<String, bool> = HashMap::new();
+        loop {
+            let message = match tokio::time::timeout(STALL_TIMEOUT, socket.next()).await {
+                Err(_) => bail!("no message for {:?}", STALL_TIMEOUT),
+                Ok(None) => return Ok(!subscriptions.is_empty()),
+                Ok(Some(message)) => message?,
+            };
+            let text = match message {
+                Message::Text(text) => text,
+                Message::Close(_) => return Ok(!subscriptions.is_empty()),
+                _ => continue,
+            };
+            let message: Value = serde_json::from_str(&text)?;
+
+            let event = if let Some(id) = message["id"].as_u64() {
+                let Some(request) = requests.get(id as usize) else {
+                    println!("WebSocket {}: ignoring response to unknown request {}", host(&self.url), message["id"]);
+                    continue;
+                };
+                if let Some(err) = message.get("error") {
+                    bail!("eth_subscribe {} rejected: {}", request, err);
+                }
+                let subscription = message["result"]
+                    .as_str()
+                    .ok_or_else(|| anyhow!("eth_subscribe returned no subscription id: {}", message))?;
+                subscriptions.insert(subscription.to_string(), id > 0);
+                if subscriptions.len() < requests.len() {
+                    continue;
+                }
+                Event::Connected
+            } else if message["method"] == "eth_subscription" {
+                let params = &message["params"];
+                let Some(&is_log) = params["subscription"].as_str().and_then(|s| subscriptions.get(s)) else {
+                    continue;
+                };
+                let result = &params["result"];
+                if is_log {
+                    // Logs removed by a reorg are handled by the follower's parent-hash check.
+                    if result["removed"] == true {
+                        continue;
+                    }
+                    Event::Log(parse_quantity(&result["blockNumber"])?)
+                } else {
+                    Event::Head(parse_quantity(&result["number"])?)
+                }
+            } else {
+                continue;
+            };
+            if tx.send(event).await.is_err() {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{test_config, FakeWs};
+
+    async fn next(events: &mut mpsc::Receiver<Event>) -> Event {
+        tokio::time::timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn forwards_heads_and_logs_and_resubscribes_after_reconnecting() {
+        let ws = FakeWs::start().await;
+        let config = test_config();
+        let (addresses, topics) = config.log_filter();
+        let mut events = Subscriber::new(ws.url.clone())
+            .with_logs(&addresses, &topics)
+            .with_reconnect_delay(Duration::from_millis(10))
+            .spawn();
+
+        assert_eq!(next(&mut events).await, Event::Connected);
+        assert_eq!(ws.subscriptions(), vec!["newHeads".to_string(), "logs".to_string()]);
+        ws.push_head(100);
+        ws.push_log(100, false);
+        ws.push_log(100, true);
+        // Responses to requests never sent are ignored rather than failing the session.
+        ws.push_raw(json!({ "jsonrpc": "2.0", "id": 7, "result": "0xbogus" }));
+        ws.push_raw(json!({ "jsonrpc": "2.0", "id": 9, "error": { "code": -32000, "message": "bogus" } }));
+        ws.push_head(101);
+        assert_eq!(next(&mut events).await, Event::Head(100));
+        assert_eq!(next(&mut events).await, Event::Log(100));
+        assert_eq!(next(&mut events).await, Event::Head(101));
+        assert_eq!(ws.connections(), 1);
+
+        // A dropped socket is reopened and subscribed again; `Indexer::follow` answers the
+        // `Connected` by filling the gap over HTTP.
+        ws.drop_connections();
+        assert_eq!(next(&mut events).await, Event::Connected);
+        assert_eq!(ws.connections(), 2);
+        ws.push_head(104);
+        assert_eq!(next(&mut events).await, Event::Head(104));
+    }
+}